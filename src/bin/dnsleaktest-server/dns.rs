@@ -0,0 +1,294 @@
+use crate::Sessions;
+use std::net::{IpAddr, UdpSocket};
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NOERROR: u16 = 0;
+const RCODE_REFUSED: u16 = 5;
+
+struct Query<'a> {
+    id: u16,
+    flags: u16,
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    question: &'a [u8],
+}
+
+impl<'a> Query<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        let read_u16 = |offset: usize| -> Option<u16> {
+            Some(u16::from_be_bytes([
+                *packet.get(offset)?,
+                *packet.get(offset + 1)?,
+            ]))
+        };
+        let id = read_u16(0)?;
+        let flags = read_u16(2)?;
+        let is_response = flags & 0x8000 != 0;
+        let opcode = (flags >> 11) & 0xF;
+        if is_response || opcode != 0 || read_u16(4)? != 1 {
+            return None;
+        }
+
+        let mut labels = Vec::new();
+        let mut offset = 12;
+        loop {
+            let len = usize::from(*packet.get(offset)?);
+            offset += 1;
+            if len == 0 {
+                break;
+            }
+            if len & 0xC0 != 0 {
+                return None;
+            }
+            let label = packet.get(offset..offset + len)?;
+            labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+            offset += len;
+        }
+        let qtype = read_u16(offset)?;
+        let qclass = read_u16(offset + 2)?;
+
+        Some(Self {
+            id,
+            flags,
+            name: labels.join("."),
+            qtype,
+            qclass,
+            question: &packet[12..offset + 4],
+        })
+    }
+
+    fn in_zone(&self, zone: &str) -> bool {
+        self.name == zone || self.subdomain(zone).is_some()
+    }
+
+    fn subdomain(&self, zone: &str) -> Option<&str> {
+        self.name.strip_suffix(zone)?.strip_suffix('.')
+    }
+
+    /// Returns the session id of a `{i}.{id}.{zone}` probe name.
+    fn session_id(&self, zone: &str) -> Option<&str> {
+        self.subdomain(zone)?.rsplit_once('.').map(|(_, id)| id)
+    }
+
+    fn response(&self, zone: &str, addresses: &[IpAddr]) -> Vec<u8> {
+        let in_zone = self.in_zone(zone);
+        let answers: Vec<&IpAddr> = addresses
+            .iter()
+            .filter(|addr| {
+                in_zone
+                    && self.qclass == CLASS_IN
+                    && match addr {
+                        IpAddr::V4(_) => self.qtype == TYPE_A,
+                        IpAddr::V6(_) => self.qtype == TYPE_AAAA,
+                    }
+            })
+            .collect();
+
+        let rcode = if in_zone {
+            RCODE_NOERROR
+        } else {
+            RCODE_REFUSED
+        };
+        let flags = 0x8000 | 0x0400 | (self.flags & 0x0100) | rcode;
+        let mut response = Vec::with_capacity(512);
+        response.extend_from_slice(&self.id.to_be_bytes());
+        response.extend_from_slice(&flags.to_be_bytes());
+        response.extend_from_slice(&1u16.to_be_bytes());
+        response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        response.extend_from_slice(&0u16.to_be_bytes());
+        response.extend_from_slice(&0u16.to_be_bytes());
+        response.extend_from_slice(self.question);
+
+        for addr in answers {
+            // Pointer to the question name, which always starts at offset 12.
+            response.extend_from_slice(&0xC00Cu16.to_be_bytes());
+            response.extend_from_slice(&self.qtype.to_be_bytes());
+            response.extend_from_slice(&CLASS_IN.to_be_bytes());
+            // A zero TTL keeps resolvers from caching the probe names.
+            response.extend_from_slice(&0u32.to_be_bytes());
+            match addr {
+                IpAddr::V4(addr) => {
+                    response.extend_from_slice(&4u16.to_be_bytes());
+                    response.extend_from_slice(&addr.octets());
+                }
+                IpAddr::V6(addr) => {
+                    response.extend_from_slice(&16u16.to_be_bytes());
+                    response.extend_from_slice(&addr.octets());
+                }
+            }
+        }
+        response
+    }
+}
+
+/// Answers queries until the process exits.
+pub fn serve(socket: UdpSocket, zone: &str, addresses: &[IpAddr], sessions: &Sessions) {
+    let mut buf = [0; 4096];
+    loop {
+        // A failed receive, such as a reset after an ICMP unreachable, only affects one query.
+        let (len, src) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Failed to receive DNS query: {e}");
+                continue;
+            }
+        };
+        let Some(query) = Query::parse(&buf[..len]) else {
+            continue;
+        };
+        if let Some(id) = query.session_id(zone) {
+            sessions.record(id, src.ip());
+        }
+        let _ = socket.send_to(&query.response(zone, addresses), src);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(flags: u16, qdcount: u16, labels: &[&[u8]], tail: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34];
+        packet.extend_from_slice(&flags.to_be_bytes());
+        packet.extend_from_slice(&qdcount.to_be_bytes());
+        packet.extend_from_slice(&[0; 6]);
+        for label in labels {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label);
+        }
+        packet.extend_from_slice(tail);
+        packet
+    }
+
+    /// A recursion-desired query for `name`, followed by `tail`.
+    fn query(name: &str, tail: &[u8]) -> Vec<u8> {
+        let labels: Vec<&[u8]> = name.split('.').map(str::as_bytes).collect();
+        packet(0x0100, 1, &labels, tail)
+    }
+
+    const A_IN: &[u8] = &[0, 0, 1, 0, 1];
+    const AAAA_IN: &[u8] = &[0, 0, 28, 0, 1];
+
+    fn rcode(response: &[u8]) -> u16 {
+        u16::from_be_bytes([response[2], response[3]]) & 0x000F
+    }
+
+    fn answer_count(response: &[u8]) -> u16 {
+        u16::from_be_bytes([response[6], response[7]])
+    }
+
+    fn addresses() -> [IpAddr; 2] {
+        ["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()]
+    }
+
+    #[test]
+    fn parse_query() {
+        let bytes = query("0.AbC.Leak.test", A_IN);
+        let parsed = Query::parse(&bytes).unwrap();
+        assert_eq!(parsed.id, 0x1234);
+        assert_eq!(parsed.name, "0.abc.leak.test");
+        assert_eq!(parsed.qtype, TYPE_A);
+        assert_eq!(parsed.qclass, CLASS_IN);
+        assert_eq!(parsed.question, &bytes[12..]);
+
+        let bytes = query("leak.test", AAAA_IN);
+        assert_eq!(Query::parse(&bytes).unwrap().qtype, TYPE_AAAA);
+    }
+
+    #[test]
+    fn parse_rejects_responses_and_other_opcodes() {
+        assert!(Query::parse(&packet(0x8000, 1, &[b"leak", b"test"], A_IN)).is_none());
+        assert!(Query::parse(&packet(0x0800, 1, &[b"leak", b"test"], A_IN)).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_other_question_counts() {
+        assert!(Query::parse(&packet(0, 0, &[b"leak", b"test"], A_IN)).is_none());
+        assert!(Query::parse(&packet(0, 2, &[b"leak", b"test"], A_IN)).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_truncated_packets() {
+        assert!(Query::parse(&[]).is_none());
+        assert!(Query::parse(&[0x12, 0x34, 0, 0, 0]).is_none());
+        assert!(Query::parse(&packet(0, 1, &[b"leak"], &[4, b't', b'e'])).is_none());
+        assert!(Query::parse(&packet(0, 1, &[b"leak", b"test"], &[])).is_none());
+        assert!(Query::parse(&packet(0, 1, &[b"leak"], &[0, 0, 1])).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_compressed_names() {
+        let bytes = packet(0, 1, &[b"leak"], &[0xC0, 0x0C, 0, 1, 0, 1]);
+        assert!(Query::parse(&bytes).is_none());
+    }
+
+    #[test]
+    fn session_id_of_probe_names() {
+        let bytes = query("3.abc.leak.test", A_IN);
+        assert_eq!(
+            Query::parse(&bytes).unwrap().session_id("leak.test"),
+            Some("abc")
+        );
+        let bytes = query("abc.leak.test", A_IN);
+        assert_eq!(Query::parse(&bytes).unwrap().session_id("leak.test"), None);
+        let bytes = query("3.abc.notleak.test", A_IN);
+        assert_eq!(Query::parse(&bytes).unwrap().session_id("leak.test"), None);
+    }
+
+    #[test]
+    fn response_answers_with_the_address_of_the_queried_family() {
+        let bytes = query("0.abc.leak.test", A_IN);
+        let response = Query::parse(&bytes)
+            .unwrap()
+            .response("leak.test", &addresses());
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(
+            u16::from_be_bytes([response[2], response[3]]) & 0x8100,
+            0x8100
+        );
+        assert_eq!(rcode(&response), RCODE_NOERROR);
+        assert_eq!(answer_count(&response), 1);
+        assert!(response.ends_with(&[192, 0, 2, 1]));
+
+        let bytes = query("leak.test", AAAA_IN);
+        let response = Query::parse(&bytes)
+            .unwrap()
+            .response("leak.test", &addresses());
+        assert_eq!(answer_count(&response), 1);
+        assert!(response.ends_with(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets()
+        ));
+    }
+
+    #[test]
+    fn response_has_no_answers_for_other_types_and_classes() {
+        let bytes = query("leak.test", &[0, 0, 16, 0, 1]);
+        let response = Query::parse(&bytes)
+            .unwrap()
+            .response("leak.test", &addresses());
+        assert_eq!(rcode(&response), RCODE_NOERROR);
+        assert_eq!(answer_count(&response), 0);
+
+        let bytes = query("leak.test", &[0, 0, 1, 0, 3]);
+        let response = Query::parse(&bytes)
+            .unwrap()
+            .response("leak.test", &addresses());
+        assert_eq!(answer_count(&response), 0);
+    }
+
+    #[test]
+    fn response_refuses_names_outside_the_zone() {
+        let bytes = query("example.com", A_IN);
+        let response = Query::parse(&bytes)
+            .unwrap()
+            .response("leak.test", &addresses());
+        assert_eq!(rcode(&response), RCODE_REFUSED);
+        assert_eq!(answer_count(&response), 0);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::Sessions;
+use dnsleaktest_tui::dns_leak::DnsData;
+use std::net::IpAddr;
+use tiny_http::{Header, Request, Response, Server};
+
+type HttpResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+pub fn serve(server: Server, sessions: &Sessions) {
+    for request in server.incoming_requests() {
+        let response = route(&request, sessions);
+        let _ = request.respond(response);
+    }
+}
+
+fn route(request: &Request, sessions: &Sessions) -> HttpResponse {
+    let path = request.url().split('?').next().unwrap_or_default();
+    if path == "/id" {
+        return Response::from_string(sessions.create());
+    }
+    if let Some(id) = path.strip_prefix("/dnsleak/test/") {
+        let Some(resolvers) = sessions.resolvers(id) else {
+            return Response::from_string("unknown test id").with_status_code(404);
+        };
+        let client = request.remote_addr().map(|addr| addr.ip());
+        let body = serde_json::to_string(&results(client, &resolvers)).unwrap_or_default();
+        let content_type =
+            Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+        return Response::from_string(body).with_header(content_type);
+    }
+    Response::from_string("ok")
+}
+
+fn results(client: Option<IpAddr>, resolvers: &[IpAddr]) -> Vec<DnsData> {
+    let mut data = Vec::new();
+    if let Some(client) = client {
+        data.push(entry(client.to_string(), "ip"));
+    }
+    data.extend(
+        resolvers
+            .iter()
+            .map(|resolver| entry(resolver.to_string(), "dns")),
+    );
+    data.push(entry(
+        conclusion(client, resolvers).to_string(),
+        "conclusion",
+    ));
+    data
+}
+
+fn entry(ip: String, type_field: &str) -> DnsData {
+    DnsData {
+        ip,
+        type_field: type_field.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Without geolocation data the best signal available is whether every
+/// resolver sits in the same network as the client's public address.
+fn conclusion(client: Option<IpAddr>, resolvers: &[IpAddr]) -> &'static str {
+    match client {
+        _ if resolvers.is_empty() => "No DNS servers found.",
+        Some(client) if resolvers.iter().all(|r| same_network(client, *r)) => "DNS is not leaking.",
+        _ => "DNS may be leaking.",
+    }
+}
+
+fn same_network(a: IpAddr, b: IpAddr) -> bool {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => a.octets()[..3] == b.octets()[..3],
+        (IpAddr::V6(a), IpAddr::V6(b)) => a.segments()[..3] == b.segments()[..3],
+        _ => false,
+    }
+}
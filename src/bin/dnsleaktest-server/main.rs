@@ -0,0 +1,116 @@
+use clap::Parser;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod dns;
+mod http;
+
+const SESSION_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "dnsleaktest-server",
+    about = "A self-hosted DNS leak test server compatible with dnsleaktest-tui"
+)]
+struct Opt {
+    #[clap(long = "zone", value_name = "DOMAIN")]
+    zone: String,
+
+    #[clap(long = "address", value_name = "IP")]
+    addresses: Vec<IpAddr>,
+
+    #[clap(long = "dns-listen", default_value = "0.0.0.0:53", value_name = "ADDR")]
+    dns_listen: SocketAddr,
+
+    #[clap(
+        long = "http-listen",
+        default_value = "0.0.0.0:8080",
+        value_name = "ADDR"
+    )]
+    http_listen: SocketAddr,
+}
+
+struct Session {
+    created: Instant,
+    resolvers: Vec<IpAddr>,
+}
+
+#[derive(Clone, Default)]
+pub struct Sessions {
+    inner: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl Sessions {
+    pub fn create(&self) -> String {
+        let mut sessions = self.inner.lock().expect("session lock poisoned");
+        sessions.retain(|_, session| session.created.elapsed() < SESSION_TTL);
+        let id = generate_id();
+        sessions.insert(
+            id.clone(),
+            Session {
+                created: Instant::now(),
+                resolvers: Vec::new(),
+            },
+        );
+        id
+    }
+
+    pub fn record(&self, id: &str, resolver: IpAddr) {
+        let mut sessions = self.inner.lock().expect("session lock poisoned");
+        if let Some(session) = sessions.get_mut(id) {
+            if !session.resolvers.contains(&resolver) {
+                session.resolvers.push(resolver);
+            }
+        }
+    }
+
+    pub fn resolvers(&self, id: &str) -> Option<Vec<IpAddr>> {
+        let sessions = self.inner.lock().expect("session lock poisoned");
+        sessions.get(id).map(|session| session.resolvers.clone())
+    }
+}
+
+fn generate_id() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    format!("{:016x}", hasher.finish())
+}
+
+fn main() -> color_eyre::Result<()> {
+    let opt = Opt::parse();
+    let zone = opt.zone.trim_end_matches('.').to_ascii_lowercase();
+    let sessions = Sessions::default();
+
+    let socket = UdpSocket::bind(opt.dns_listen)?;
+    println!("Serving DNS for {zone} on {}", opt.dns_listen);
+    let dns_sessions = sessions.clone();
+    let dns_zone = zone.clone();
+    let dns = thread::spawn(move || dns::serve(socket, &dns_zone, &opt.addresses, &dns_sessions));
+
+    let server = tiny_http::Server::http(opt.http_listen)
+        .map_err(|e| color_eyre::eyre::eyre!("failed to start HTTP server: {e}"))?;
+    println!("Serving leak test API on http://{}", opt.http_listen);
+    let http = thread::spawn(move || http::serve(server, &sessions));
+
+    // Sessions handed out over HTTP can only collect resolvers while DNS is served, so the
+    // server exits as soon as either side stops.
+    loop {
+        if dns.is_finished() {
+            color_eyre::eyre::bail!("the DNS server stopped");
+        }
+        if http.is_finished() {
+            color_eyre::eyre::bail!("the HTTP server stopped");
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::dns_leak::{Asn, Conclusion, CountryCode, LeakError, LeakReport, LeakResult};
+use crate::policy::Policy;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::process::ExitCode;
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct CheckOptions {
+    /// Resolver ASN that is allowed to see DNS queries (e.g. AS13335).
+    #[clap(long = "allow-asn", value_name = "ASN")]
+    pub allowed_asns: Vec<Asn>,
+
+    /// Resolver country code that is allowed to see DNS queries (e.g. NL).
+    #[clap(long = "allow-country", value_name = "CODE")]
+    pub allowed_countries: Vec<CountryCode>,
+}
+
+impl CheckOptions {
+    /// Merges the allowed ASNs and countries given on the command line into `policy`.
+    pub fn apply(&self, policy: &mut Policy) {
+        policy
+            .asns
+            .extend(self.allowed_asns.iter().map(|asn| asn.number));
+        policy
+            .countries
+            .extend(self.allowed_countries.iter().cloned());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    NoLeak,
+    Leak,
+    Inconclusive,
+    NetworkError,
+    /// The provider answered with something that is not a leak test result.
+    InvalidResponse,
+}
+
+/// Exit code of `check` when it could not run, so a typo in its options never reads as a verdict.
+pub const CONFIG_ERROR_EXIT_CODE: u8 = 4;
+
+impl Verdict {
+    pub fn exit_code(self) -> ExitCode {
+        match self {
+            Self::NoLeak => ExitCode::from(0),
+            Self::Leak => ExitCode::from(1),
+            Self::Inconclusive => ExitCode::from(2),
+            Self::NetworkError => ExitCode::from(3),
+            Self::InvalidResponse => ExitCode::from(5),
+        }
+    }
+}
+
+impl Display for Verdict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::NoLeak => write!(f, "no leak"),
+            Self::Leak => write!(f, "leak detected"),
+            Self::Inconclusive => write!(f, "inconclusive"),
+            Self::NetworkError => write!(f, "network error"),
+            Self::InvalidResponse => write!(f, "invalid response"),
+        }
+    }
+}
+
+pub fn verdict(report: &LeakReport, policy: &Policy) -> Verdict {
+    if !policy.is_empty() {
+        if report.resolvers.is_empty() {
+            Verdict::Inconclusive
+        } else if report
+            .resolvers
+            .iter()
+            .all(|r| policy.evaluate(r).is_allowed())
+        {
+            Verdict::NoLeak
+        } else {
+            Verdict::Leak
+        }
+    } else {
+        match report.conclusion {
+            Conclusion::NoLeak => Verdict::NoLeak,
+            Conclusion::PossibleLeak => Verdict::Leak,
+            Conclusion::Unknown => Verdict::Inconclusive,
+        }
+    }
+}
+
+/// The verdict over all leak tests of a run, where any leak outweighs the rest.
+///
+/// A family that could not be tested leaves the run inconclusive, unless none could. Then an
+/// invalid response outweighs network errors, as retrying will not help.
+pub fn combined_verdict(results: &[LeakResult], policy: &Policy) -> Verdict {
+    let verdicts: Vec<Verdict> = results
+        .iter()
+        .map(|result| match result {
+            Ok(report) => verdict(report, policy),
+            Err(LeakError::Network(_)) => Verdict::NetworkError,
+            Err(LeakError::InvalidResponse(_)) => Verdict::InvalidResponse,
+        })
+        .collect();
+    let failed = |v: &Verdict| matches!(v, Verdict::NetworkError | Verdict::InvalidResponse);
+    if verdicts.contains(&Verdict::Leak) {
+        Verdict::Leak
+    } else if verdicts.iter().all(failed) && verdicts.contains(&Verdict::InvalidResponse) {
+        Verdict::InvalidResponse
+    } else if verdicts.iter().all(failed) {
+        Verdict::NetworkError
+    } else if verdicts
+        .iter()
+        .any(|v| *v == Verdict::Inconclusive || failed(v))
+    {
+        Verdict::Inconclusive
+    } else {
+        Verdict::NoLeak
+    }
+}
+
+pub fn run_check(results: Vec<LeakResult>, policy: &Policy) -> ExitCode {
+    for result in &results {
+        let report = match result {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("error: {e}");
+                continue;
+            }
+        };
+        let prefix = report
+            .family
+            .map(|family| format!("{family}: "))
+            .unwrap_or_default();
+        for resolver in &report.resolvers {
+            let status = if policy.is_empty() {
+                String::from("ok")
+            } else {
+                policy.evaluate(resolver).to_string()
+            };
+            println!(
+                "{prefix}{} [{}, {}] {status}",
+                resolver.ip,
+                resolver
+                    .country
+                    .as_ref()
+                    .map(CountryCode::to_string)
+                    .unwrap_or_default(),
+                resolver.asn_label()
+            );
+        }
+    }
+
+    let verdict = combined_verdict(&results, policy);
+    println!("{verdict}");
+    verdict.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(conclusion: Conclusion) -> LeakResult {
+        Ok(LeakReport {
+            conclusion,
+            ..Default::default()
+        })
+    }
+
+    fn network_error() -> LeakResult {
+        Err(LeakError::Network(String::from("IPv6 leak test failed")))
+    }
+
+    fn invalid_response() -> LeakResult {
+        Err(LeakError::InvalidResponse(String::from("unknown type")))
+    }
+
+    fn combined(results: &[LeakResult]) -> Verdict {
+        combined_verdict(results, &Policy::default())
+    }
+
+    #[test]
+    fn verdict_of_a_single_test() {
+        assert_eq!(combined(&[report(Conclusion::NoLeak)]), Verdict::NoLeak);
+        assert_eq!(combined(&[report(Conclusion::PossibleLeak)]), Verdict::Leak);
+        assert_eq!(
+            combined(&[report(Conclusion::Unknown)]),
+            Verdict::Inconclusive
+        );
+        assert_eq!(combined(&[network_error()]), Verdict::NetworkError);
+        assert_eq!(combined(&[invalid_response()]), Verdict::InvalidResponse);
+    }
+
+    #[test]
+    fn any_leak_is_a_leak() {
+        let results = [report(Conclusion::NoLeak), report(Conclusion::PossibleLeak)];
+        assert_eq!(combined(&results), Verdict::Leak);
+        let results = [report(Conclusion::PossibleLeak), network_error()];
+        assert_eq!(combined(&results), Verdict::Leak);
+    }
+
+    #[test]
+    fn an_untested_family_is_inconclusive() {
+        let results = [report(Conclusion::NoLeak), report(Conclusion::NoLeak)];
+        assert_eq!(combined(&results), Verdict::NoLeak);
+        let results = [report(Conclusion::Unknown), report(Conclusion::NoLeak)];
+        assert_eq!(combined(&results), Verdict::Inconclusive);
+        let results = [report(Conclusion::NoLeak), network_error()];
+        assert_eq!(combined(&results), Verdict::Inconclusive);
+        let results = [report(Conclusion::NoLeak), invalid_response()];
+        assert_eq!(combined(&results), Verdict::Inconclusive);
+    }
+
+    #[test]
+    fn invalid_responses_outweigh_network_errors() {
+        assert_eq!(
+            combined(&[network_error(), network_error()]),
+            Verdict::NetworkError
+        );
+        assert_eq!(
+            combined(&[network_error(), invalid_response()]),
+            Verdict::InvalidResponse
+        );
+    }
+}
@@ -1,5 +1,21 @@
+use clap::ValueEnum;
+use color_eyre::eyre::eyre;
+use dns_lookup::{AddrFamily, AddrInfoHints, SockType};
+use reqwest::blocking::Client;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
-const API_URL: &str = "bash.ws";
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+
+pub const DEFAULT_API_URL: &str = "https://bash.ws";
+
+const PROBE_COUNT: usize = 10;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -13,24 +29,623 @@ pub struct DnsData {
     pub type_field: String,
 }
 
-pub fn test_dns_leak() -> color_eyre::Result<Vec<DnsData>> {
-    let id = reqwest::blocking::get(format!("https://{API_URL}/id"))?.text()?;
+/// An autonomous system number and the organization it is registered to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Asn {
+    pub number: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+}
 
-    let attempts = 0..10;
-    attempts.into_iter().for_each(|i| {
-        let _ = reqwest::blocking::get(format!("https://{i}.{id}.{API_URL}")).ok();
-    });
+impl FromStr for Asn {
+    type Err = color_eyre::Report;
 
-    let mut data: Vec<DnsData> =
-        reqwest::blocking::get(format!("https://{API_URL}/dnsleak/test/{id}?json"))?.json()?;
+    /// Parses `AS13335`, `13335` or `AS13335 Cloudflare, Inc.`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let (number, organization) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let digits = number
+            .strip_prefix("AS")
+            .or_else(|| number.strip_prefix("as"))
+            .unwrap_or(number);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(eyre!("invalid ASN: {text}"));
+        }
+        let organization = organization.trim();
+        Ok(Self {
+            number: digits.parse().map_err(|_| eyre!("invalid ASN: {text}"))?,
+            organization: (!organization.is_empty()).then(|| organization.to_string()),
+        })
+    }
+}
 
-    data.iter_mut().for_each(|result| {
-        result.country_name = format!(
-            "{} {}",
-            result.country_name,
-            country_emoji::flag(&result.country).unwrap_or_else(|| "?".to_string())
-        );
+impl Display for Asn {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AS{}", self.number)?;
+        if let Some(organization) = &self.organization {
+            write!(f, " {organization}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An upper case ISO 3166-1 alpha-2 country code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CountryCode(String);
+
+impl CountryCode {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for CountryCode {
+    type Err = color_eyre::Report;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        if text.len() != 2 || !text.bytes().all(|b| b.is_ascii_alphabetic()) {
+            return Err(eyre!("invalid country code: {text}"));
+        }
+        Ok(Self(text.to_ascii_uppercase()))
+    }
+}
+
+impl TryFrom<String> for CountryCode {
+    type Error = color_eyre::Report;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<CountryCode> for String {
+    fn from(code: CountryCode) -> Self {
+        code.0
+    }
+}
+
+impl Display for CountryCode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub ip: IpAddr,
+    pub country: Option<CountryCode>,
+    pub country_name: String,
+    pub asn: Option<Asn>,
+}
+
+impl Endpoint {
+    pub fn flag(&self) -> String {
+        self.country
+            .as_ref()
+            .and_then(|country| country_emoji::flag(country.as_str()))
+            .unwrap_or_else(|| "?".to_string())
+    }
+
+    /// The ASN and organization as shown in tables, empty if unknown.
+    pub fn asn_label(&self) -> String {
+        self.asn.as_ref().map(Asn::to_string).unwrap_or_default()
+    }
+}
+
+impl TryFrom<DnsData> for Endpoint {
+    type Error = color_eyre::Report;
+
+    fn try_from(data: DnsData) -> Result<Self, Self::Error> {
+        Ok(Self {
+            ip: data
+                .ip
+                .parse()
+                .map_err(|_| eyre!("invalid IP address in leak test result: {}", data.ip))?,
+            country: parse_optional(&data.country),
+            country_name: data.country_name,
+            asn: parse_optional(&data.asn),
+        })
+    }
+}
+
+/// Parses a field of the API that is left empty, or filled with free text, when the value is
+/// unknown, so that one odd resolver does not fail the whole report.
+fn parse_optional<T: FromStr>(text: &str) -> Option<T> {
+    text.parse().ok()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Conclusion {
+    NoLeak,
+    PossibleLeak,
+    #[default]
+    Unknown,
+}
+
+impl Conclusion {
+    fn parse(text: &str) -> Self {
+        let text = text.to_lowercase();
+        if text.contains("not leaking") {
+            Self::NoLeak
+        } else if text.contains("leaking") {
+            Self::PossibleLeak
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+impl Display for Conclusion {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::NoLeak => write!(f, "DNS is not leaking."),
+            Self::PossibleLeak => write!(f, "DNS may be leaking."),
+            Self::Unknown => write!(f, "Unable to determine whether DNS is leaking."),
+        }
+    }
+}
+
+/// The address family a leak test was forced to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl IpFamily {
+    /// Resolves `host` with A-only or AAAA-only queries.
+    fn lookup(self, host: &str) -> io::Result<Vec<IpAddr>> {
+        let address = match self {
+            Self::Ipv4 => AddrFamily::Inet,
+            Self::Ipv6 => AddrFamily::Inet6,
+        };
+        let hints = AddrInfoHints {
+            flags: 0,
+            address: address.into(),
+            socktype: SockType::Stream.into(),
+            protocol: 0,
+        };
+        dns_lookup::getaddrinfo(Some(host), None, Some(hints))
+            .map_err(io::Error::from)?
+            .map(|info| info.map(|info| info.sockaddr.ip()))
+            .collect()
+    }
+
+    fn unspecified(self) -> IpAddr {
+        match self {
+            Self::Ipv4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            Self::Ipv6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+impl Display for IpFamily {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Ipv4 => write!(f, "IPv4"),
+            Self::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// A resolver for reqwest that only looks up addresses of a single family.
+struct FamilyResolver(IpFamily);
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.0;
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            // getaddrinfo blocks, which would stall the runtime the requests are driven on.
+            let addrs = tokio::task::spawn_blocking(move || family.lookup(&host)).await??;
+            let addrs: Addrs = Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+fn http_client(family: Option<IpFamily>) -> color_eyre::Result<Client> {
+    let builder = Client::builder();
+    let builder = match family {
+        Some(family) => builder
+            .local_address(family.unspecified())
+            .dns_resolver(Arc::new(FamilyResolver(family))),
+        None => builder,
+    };
+    Ok(builder.build()?)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeakReport {
+    #[serde(default)]
+    pub family: Option<IpFamily>,
+    pub ip: Option<Endpoint>,
+    pub resolvers: Vec<Endpoint>,
+    pub conclusion: Conclusion,
+}
+
+impl TryFrom<Vec<DnsData>> for LeakReport {
+    type Error = color_eyre::Report;
+
+    fn try_from(data: Vec<DnsData>) -> Result<Self, Self::Error> {
+        let mut report = Self::default();
+        for entry in data {
+            match entry.type_field.as_str() {
+                "ip" => report.ip = Some(entry.try_into()?),
+                "dns" => report.resolvers.push(entry.try_into()?),
+                "conclusion" => report.conclusion = Conclusion::parse(&entry.ip),
+                other => return Err(eyre!("unknown leak test result type: {other}")),
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// A service that can tell which resolvers looked up the names it handed out.
+pub trait LeakProvider {
+    /// Acquires a new test session id.
+    fn session_id(&self) -> color_eyre::Result<String>;
+
+    /// Makes the local resolvers look up the probe names of the session.
+    fn trigger_probes(&self, id: &str) -> color_eyre::Result<()>;
+
+    /// Fetches the results of the session.
+    fn fetch_results(&self, id: &str) -> color_eyre::Result<Vec<DnsData>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Provider {
+    /// bash.ws or any service speaking its protocol, probed with HTTP requests.
+    BashWs,
+    /// dnsleaktest-server, probed with plain DNS lookups.
+    SelfHosted,
+}
+
+impl Provider {
+    /// Builds the provider, forcing all of its traffic to `family` if given.
+    pub fn build(
+        self,
+        api_url: Option<Url>,
+        family: Option<IpFamily>,
+    ) -> color_eyre::Result<Box<dyn LeakProvider>> {
+        match self {
+            Self::BashWs => {
+                let api_url = match api_url {
+                    Some(api_url) => api_url,
+                    None => Url::parse(DEFAULT_API_URL)?,
+                };
+                Ok(Box::new(BashWs::new(api_url, family)?))
+            }
+            Self::SelfHosted => {
+                let api_url = api_url
+                    .ok_or_else(|| eyre!("the self-hosted provider requires --leak-api-url"))?;
+                Ok(Box::new(SelfHosted::new(api_url, family)?))
+            }
+        }
+    }
+}
+
+pub struct BashWs {
+    api_url: Url,
+    host: String,
+    client: Client,
+}
+
+impl BashWs {
+    pub fn new(api_url: Url, family: Option<IpFamily>) -> color_eyre::Result<Self> {
+        let host = api_url
+            .host_str()
+            .ok_or_else(|| eyre!("leak test API URL has no host: {api_url}"))?
+            .to_string();
+        Ok(Self {
+            api_url,
+            host,
+            client: http_client(family)?,
+        })
+    }
+}
+
+impl LeakProvider for BashWs {
+    fn session_id(&self) -> color_eyre::Result<String> {
+        fetch_session_id(&self.client, &self.api_url)
+    }
+
+    fn trigger_probes(&self, id: &str) -> color_eyre::Result<()> {
+        (0..PROBE_COUNT).for_each(|i| {
+            let mut probe_url = self.api_url.clone();
+            if probe_url
+                .set_host(Some(&format!("{i}.{id}.{}", self.host)))
+                .is_ok()
+            {
+                let _ = self.client.get(probe_url).send().ok();
+            }
+        });
+        Ok(())
+    }
+
+    fn fetch_results(&self, id: &str) -> color_eyre::Result<Vec<DnsData>> {
+        fetch_results(&self.client, &self.api_url, id)
+    }
+}
+
+pub struct SelfHosted {
+    api_url: Url,
+    zone: String,
+    family: Option<IpFamily>,
+    client: Client,
+}
+
+impl SelfHosted {
+    pub fn new(api_url: Url, family: Option<IpFamily>) -> color_eyre::Result<Self> {
+        let zone = api_url
+            .host_str()
+            .ok_or_else(|| eyre!("leak test API URL has no host: {api_url}"))?
+            .to_string();
+        Ok(Self {
+            api_url,
+            zone,
+            family,
+            client: http_client(family)?,
+        })
+    }
+}
+
+impl LeakProvider for SelfHosted {
+    fn session_id(&self) -> color_eyre::Result<String> {
+        fetch_session_id(&self.client, &self.api_url)
+    }
+
+    fn trigger_probes(&self, id: &str) -> color_eyre::Result<()> {
+        (0..PROBE_COUNT).for_each(|i| {
+            let name = format!("{i}.{id}.{}", self.zone);
+            match self.family {
+                Some(family) => {
+                    let _ = family.lookup(&name);
+                }
+                None => {
+                    let _ = (name, 0).to_socket_addrs();
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn fetch_results(&self, id: &str) -> color_eyre::Result<Vec<DnsData>> {
+        fetch_results(&self.client, &self.api_url, id)
+    }
+}
+
+fn fetch_session_id(client: &Client, api_url: &Url) -> color_eyre::Result<String> {
+    let id = client.get(api_url.join("/id")?).send()?.text()?;
+    Ok(id.trim().to_string())
+}
+
+fn fetch_results(client: &Client, api_url: &Url, id: &str) -> color_eyre::Result<Vec<DnsData>> {
+    let url = api_url.join(&format!("/dnsleak/test/{id}?json"))?;
+    Ok(client.get(url).send()?.json()?)
+}
+
+pub fn test_dns_leak(provider: &dyn LeakProvider) -> Result<LeakReport, LeakError> {
+    let id = provider.session_id().map_err(LeakError::from_request)?;
+    provider
+        .trigger_probes(&id)
+        .map_err(LeakError::from_request)?;
+    let data = provider
+        .fetch_results(&id)
+        .map_err(LeakError::from_request)?;
+    data.try_into()
+        .map_err(|e| LeakError::InvalidResponse(format!("invalid leak test response: {e}")))
+}
+
+/// A provider per address family to test: IPv4 and IPv6 when `ipv6` is set, otherwise any.
+pub type LeakTests = Vec<(Option<IpFamily>, Box<dyn LeakProvider>)>;
+
+/// Builds the providers of every leak test, which fails on configuration errors only.
+pub fn build_leak_tests(
+    provider: Provider,
+    api_url: Option<Url>,
+    ipv6: bool,
+) -> color_eyre::Result<LeakTests> {
+    let families = if ipv6 {
+        vec![Some(IpFamily::Ipv4), Some(IpFamily::Ipv6)]
+    } else {
+        vec![None]
+    };
+    families
+        .into_iter()
+        .map(|family| Ok((family, provider.build(api_url.clone(), family)?)))
+        .collect()
+}
+
+/// Why a leak test failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeakError {
+    /// The provider could not be reached.
+    Network(String),
+    /// The provider answered with something that is not a leak test result.
+    InvalidResponse(String),
+}
+
+impl LeakError {
+    fn from_request(e: color_eyre::Report) -> Self {
+        let decode = e
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_decode);
+        if decode {
+            Self::InvalidResponse(format!("invalid leak test response: {e}"))
+        } else {
+            Self::Network(format!("{e}"))
+        }
+    }
+
+    fn map_message(self, f: impl FnOnce(String) -> String) -> Self {
+        match self {
+            Self::Network(message) => Self::Network(f(message)),
+            Self::InvalidResponse(message) => Self::InvalidResponse(f(message)),
+        }
+    }
+}
+
+impl Display for LeakError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Network(message) | Self::InvalidResponse(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// The leak test of one address family, which fails independently of the other.
+pub type LeakResult = Result<LeakReport, LeakError>;
+
+pub fn run_leak_tests(tests: &LeakTests) -> Vec<LeakResult> {
+    tests
+        .iter()
+        .map(|(family, provider)| {
+            let mut report = test_dns_leak(provider.as_ref()).map_err(|e| match family {
+                Some(family) => e.map_message(|e| format!("{family} leak test failed: {e}")),
+                None => e,
+            })?;
+            report.family = *family;
+            Ok(report)
+        })
+        .collect()
+}
+
+/// Runs the leak tests on a background thread and sends the outcome once they finish.
+pub fn spawn_leak_tests(
+    provider: Provider,
+    api_url: Option<Url>,
+    ipv6: bool,
+    results: Sender<Vec<LeakResult>>,
+) {
+    thread::spawn(move || {
+        // The options were checked at startup, only setting up the HTTP client can fail here.
+        let result = match build_leak_tests(provider, api_url, ipv6) {
+            Ok(tests) => run_leak_tests(&tests),
+            Err(e) => vec![Err(LeakError::Network(e.to_string()))],
+        };
+        let _ = results.send(result);
     });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_asn() {
+        let asn: Asn = "AS13335".parse().unwrap();
+        assert_eq!(asn.number, 13335);
+        assert_eq!(asn.organization, None);
+        assert_eq!("as13335".parse::<Asn>().unwrap().number, 13335);
+        assert_eq!("13335".parse::<Asn>().unwrap().number, 13335);
+        assert_eq!("AS4294967295".parse::<Asn>().unwrap().number, u32::MAX);
+    }
+
+    #[test]
+    fn parse_asn_with_organization() {
+        let asn: Asn = " AS9009 M247 Europe SRL ".parse().unwrap();
+        assert_eq!(asn.number, 9009);
+        assert_eq!(asn.organization.as_deref(), Some("M247 Europe SRL"));
+    }
+
+    #[test]
+    fn reject_invalid_asn() {
+        assert!("".parse::<Asn>().is_err());
+        assert!("AS".parse::<Asn>().is_err());
+        assert!("ASX".parse::<Asn>().is_err());
+        assert!("AS+1".parse::<Asn>().is_err());
+        assert!("AS4294967296".parse::<Asn>().is_err());
+        assert!("Cloudflare".parse::<Asn>().is_err());
+    }
+
+    #[test]
+    fn display_asn() {
+        assert_eq!("as13335".parse::<Asn>().unwrap().to_string(), "AS13335");
+        let asn = "AS9009 M247 Europe SRL".parse::<Asn>().unwrap();
+        assert_eq!(asn.to_string(), "AS9009 M247 Europe SRL");
+    }
+
+    #[test]
+    fn parse_country_code() {
+        assert_eq!("NL".parse::<CountryCode>().unwrap().as_str(), "NL");
+        assert_eq!(" de ".parse::<CountryCode>().unwrap().as_str(), "DE");
+        assert!("N".parse::<CountryCode>().is_err());
+        assert!("NLD".parse::<CountryCode>().is_err());
+        assert!("N1".parse::<CountryCode>().is_err());
+    }
 
-    Ok(data)
+    fn data(ip: &str, country: &str, asn: &str, type_field: &str) -> DnsData {
+        DnsData {
+            ip: ip.to_string(),
+            country: country.to_string(),
+            country_name: String::from("Netherlands"),
+            asn: asn.to_string(),
+            type_field: type_field.to_string(),
+        }
+    }
+
+    #[test]
+    fn report_from_dns_data() {
+        let report = LeakReport::try_from(vec![
+            data("198.51.100.7", "NL", "AS9009 M247 Europe SRL", "ip"),
+            data("192.0.2.53", "nl", "AS13335", "dns"),
+            data("2001:db8::53", "", "", "dns"),
+            data("DNS is not leaking.", "", "", "conclusion"),
+        ])
+        .unwrap();
+        let ip = report.ip.unwrap();
+        assert_eq!(ip.ip, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(ip.asn.unwrap().number, 9009);
+        assert_eq!(report.resolvers.len(), 2);
+        assert_eq!(report.resolvers[0].country.as_ref().unwrap().as_str(), "NL");
+        assert_eq!(report.resolvers[0].asn.as_ref().unwrap().organization, None);
+        assert_eq!(report.resolvers[1].country, None);
+        assert_eq!(report.resolvers[1].asn, None);
+        assert_eq!(report.conclusion, Conclusion::NoLeak);
+    }
+
+    #[test]
+    fn report_keeps_resolvers_with_unknown_asn_or_country() {
+        let report = LeakReport::try_from(vec![
+            data("192.0.2.53", "Netherlands", "Cloudflare", "dns"),
+            data("192.0.2.54", "NL", "AS", "dns"),
+        ])
+        .unwrap();
+        assert_eq!(report.resolvers.len(), 2);
+        assert_eq!(report.resolvers[0].country, None);
+        assert_eq!(report.resolvers[0].asn, None);
+        assert_eq!(report.resolvers[0].country_name, "Netherlands");
+        assert_eq!(report.resolvers[1].country.as_ref().unwrap().as_str(), "NL");
+        assert_eq!(report.resolvers[1].asn, None);
+    }
+
+    #[test]
+    fn report_rejects_invalid_ip() {
+        let report = LeakReport::try_from(vec![data("not an ip", "NL", "AS1", "dns")]);
+        assert!(report.is_err());
+    }
+
+    #[test]
+    fn report_rejects_unknown_type() {
+        let report = LeakReport::try_from(vec![data("192.0.2.53", "NL", "AS1", "resolver")]);
+        assert!(report.is_err());
+    }
+
+    #[test]
+    fn parse_conclusion() {
+        assert_eq!(Conclusion::parse("DNS is not leaking."), Conclusion::NoLeak);
+        assert_eq!(
+            Conclusion::parse("DNS may be leaking."),
+            Conclusion::PossibleLeak
+        );
+        assert_eq!(
+            Conclusion::parse("No DNS servers found."),
+            Conclusion::Unknown
+        );
+    }
 }
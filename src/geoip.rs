@@ -0,0 +1,113 @@
+use crate::dns_leak::{Asn, CountryCode, Endpoint, LeakReport};
+use color_eyre::eyre::WrapErr;
+use maxminddb::{geoip2, Reader};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// Local MaxMind-format country, city and ASN databases.
+#[derive(Default)]
+pub struct GeoIp {
+    readers: Vec<Reader<Vec<u8>>>,
+}
+
+/// What the databases know about an address.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeoInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<CountryCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<Asn>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+}
+
+impl GeoInfo {
+    /// Returns the `(longitude, latitude)` of the address, if a city database knew it.
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        Some((self.longitude?, self.latitude?))
+    }
+}
+
+impl GeoIp {
+    pub fn load(paths: &[PathBuf]) -> color_eyre::Result<Self> {
+        let readers = paths
+            .iter()
+            .map(|path| {
+                Reader::open_readfile(path)
+                    .wrap_err_with(|| format!("failed to open GeoIP database {}", path.display()))
+            })
+            .collect::<color_eyre::Result<_>>()?;
+        Ok(Self { readers })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.readers.is_empty()
+    }
+
+    /// Merges the records of every database, the first one to know a field wins.
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let mut info = GeoInfo::default();
+        for reader in &self.readers {
+            if let Ok(city) = reader.lookup::<geoip2::City>(ip) {
+                if let Some(location) = city.location {
+                    if info.coordinates().is_none() {
+                        info.latitude = location.latitude;
+                        info.longitude = location.longitude;
+                    }
+                }
+                if let Some(country) = city.country {
+                    info.country = info
+                        .country
+                        .or_else(|| country.iso_code.and_then(|code| code.parse().ok()));
+                    info.country_name = info.country_name.or_else(|| {
+                        country
+                            .names
+                            .and_then(|names| names.get("en").map(|name| name.to_string()))
+                    });
+                }
+            }
+            if let Ok(asn) = reader.lookup::<geoip2::Asn>(ip) {
+                if let Some(number) = asn.autonomous_system_number {
+                    info.asn = info.asn.or_else(|| {
+                        Some(Asn {
+                            number,
+                            organization: asn.autonomous_system_organization.map(String::from),
+                        })
+                    });
+                }
+            }
+        }
+        (info != GeoInfo::default()).then_some(info)
+    }
+
+    /// Replaces the geodata of the API with that of the databases, where they know better.
+    pub fn enrich_reports<'a>(&self, reports: impl IntoIterator<Item = &'a mut LeakReport>) {
+        for report in reports {
+            report
+                .ip
+                .iter_mut()
+                .chain(report.resolvers.iter_mut())
+                .for_each(|endpoint| self.enrich_endpoint(endpoint));
+        }
+    }
+
+    fn enrich_endpoint(&self, endpoint: &mut Endpoint) {
+        let Some(info) = self.lookup(endpoint.ip) else {
+            return;
+        };
+        if info.country.is_some() {
+            endpoint.country = info.country;
+        }
+        if let Some(country_name) = info.country_name {
+            endpoint.country_name = country_name;
+        }
+        if info.asn.is_some() {
+            endpoint.asn = info.asn;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    check::{self, Verdict},
+    dns_leak::{LeakReport, LeakResult},
+    policy::Policy,
+    trace::TraceData,
+    validation::Hostname,
+};
+use chrono::{DateTime, Local};
+use clap::Args;
+use color_eyre::eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A leak test and the traceroutes that were shown next to it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub timestamp: DateTime<Local>,
+    pub target: String,
+    pub verdict: Verdict,
+    pub dns_leak: Vec<LeakReport>,
+    pub traceroutes: Vec<TraceData>,
+}
+
+impl Run {
+    /// Only the reports of families that could be tested are kept, the verdict covers all.
+    pub fn new(
+        target: &Hostname,
+        dns_leak: Vec<LeakResult>,
+        traceroutes: Vec<TraceData>,
+        policy: &Policy,
+    ) -> Self {
+        Self {
+            timestamp: Local::now(),
+            target: target.to_string(),
+            verdict: check::combined_verdict(&dns_leak, policy),
+            dns_leak: dns_leak.into_iter().flatten().collect(),
+            traceroutes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct HistoryOptions {
+    /// Number of most recent runs to list.
+    #[clap(long = "limit", default_value_t = 20, value_name = "COUNT")]
+    pub limit: usize,
+}
+
+/// The JSON-lines file under the XDG data directory that runs are appended to.
+pub fn path() -> color_eyre::Result<PathBuf> {
+    let data_dir = dirs::data_dir()
+        .ok_or_else(|| color_eyre::eyre::eyre!("failed to determine the data directory"))?;
+    Ok(data_dir.join("dnsleaktest-tui").join("history.jsonl"))
+}
+
+pub fn append(run: &Run) -> color_eyre::Result<()> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .wrap_err_with(|| format!("failed to open history {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(run)?)?;
+    Ok(())
+}
+
+/// Reads every stored run, oldest first, with a warning for each line that could not be read.
+pub fn load() -> color_eyre::Result<(Vec<Run>, Vec<String>)> {
+    let path = path()?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(parse(&contents, &path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok((Vec::new(), Vec::new())),
+        Err(e) => Err(e).wrap_err_with(|| format!("failed to read history {}", path.display())),
+    }
+}
+
+/// Parses the lines of a history file.
+///
+/// The file is only ever appended to, so a line cut short by an interrupted write or left by an
+/// older version is skipped instead of hiding every other run.
+fn parse(contents: &str, path: &Path) -> (Vec<Run>, Vec<String>) {
+    let mut runs = Vec::new();
+    let mut warnings = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(run) => runs.push(run),
+            Err(e) => warnings.push(format!("skipped history {}:{}: {e}", path.display(), i + 1)),
+        }
+    }
+    (runs, warnings)
+}
+
+/// One line per run: when, where to, the verdict and how many resolvers and hops were seen.
+pub fn summary(run: &Run) -> String {
+    let resolvers: usize = run
+        .dns_leak
+        .iter()
+        .map(|report| report.resolvers.len())
+        .sum();
+    let hops: usize = run.traceroutes.iter().map(TraceData::hop_count).sum();
+    format!(
+        "{}  {}  {}  {resolvers} resolvers  {hops} hops",
+        run.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        run.target,
+        run.verdict
+    )
+}
+
+pub fn print_history(options: &HistoryOptions) -> color_eyre::Result<()> {
+    let (runs, warnings) = load()?;
+    for warning in warnings {
+        eprintln!("warning: {warning}");
+    }
+    for run in runs.iter().rev().take(options.limit).rev() {
+        println!("{}", summary(run));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUN: &str = r#"{"timestamp":"2024-09-01T12:00:00+02:00","target":"discord.com","verdict":"no_leak","dns_leak":[],"traceroutes":[]}"#;
+
+    #[test]
+    fn parse_runs() {
+        let contents = format!("{RUN}\n\n{RUN}\n");
+        let (runs, warnings) = parse(&contents, Path::new("history.jsonl"));
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].target, "discord.com");
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn skip_unreadable_lines() {
+        let truncated = &RUN[..RUN.len() / 2];
+        let contents = format!("{RUN}\n{truncated}\n{{\"timestamp\":\"old\"}}\n{RUN}");
+        let (runs, warnings) = parse(&contents, Path::new("history.jsonl"));
+        assert_eq!(runs.len(), 2);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("skipped history history.jsonl:2: "));
+        assert!(warnings[1].starts_with("skipped history history.jsonl:3: "));
+    }
+}
@@ -0,0 +1,10 @@
+pub mod check;
+pub mod dns_leak;
+pub mod geoip;
+pub mod history;
+pub mod policy;
+pub mod report;
+pub mod table;
+pub mod trace;
+pub mod tui;
+pub mod validation;
@@ -1,31 +1,176 @@
-use clap::Parser;
-
-pub mod dns_leak;
-pub mod trace;
-pub mod tui;
-pub mod validation;
+use clap::{CommandFactory, Parser, Subcommand};
+use dnsleaktest_tui::{
+    check,
+    dns_leak::{self, LeakReport},
+    geoip::GeoIp,
+    history,
+    policy::Policy,
+    report, trace, tui, validation,
+};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 #[derive(Debug, Parser)]
 #[clap(name = "dnsleaktest-tui", about)]
 struct Opt {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     #[clap(
         long = "hostname",
         default_value = "discord.com",
         value_name = "STRING"
     )]
     hostname: String,
+
+    #[clap(flatten)]
+    trace_options: trace::TraceOptions,
+
+    #[clap(long = "leak-api-url", value_name = "URL", global = true)]
+    leak_api_url: Option<reqwest::Url>,
+
+    #[clap(
+        long = "provider",
+        value_enum,
+        default_value_t = dns_leak::Provider::BashWs,
+        global = true
+    )]
+    provider: dns_leak::Provider,
+
+    /// Run a second, IPv6-only leak test next to an IPv4-only one.
+    #[clap(long = "ipv6-leak-test", global = true)]
+    ipv6_leak_test: bool,
+
+    #[clap(long = "policy", value_name = "FILE", global = true)]
+    policy: Option<PathBuf>,
+
+    /// MaxMind-format country, city or ASN database to look up addresses in (repeatable).
+    #[clap(long = "geoip-db", value_name = "FILE", global = true)]
+    geoip_dbs: Vec<PathBuf>,
+
+    /// Do not append this run to the local history.
+    #[clap(long = "no-history", global = true)]
+    no_history: bool,
+
+    #[clap(
+        long = "output",
+        value_enum,
+        default_value_t = report::OutputFormat::Tui
+    )]
+    output: report::OutputFormat,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the DNS leak test headlessly and exit with a code that encodes the verdict.
+    Check(check::CheckOptions),
+    /// List the runs stored in the local history.
+    History(history::HistoryOptions),
 }
 
-fn main() -> color_eyre::Result<()> {
-    let opt = Opt::parse();
-    let hostname = validation::Hostname::new(opt.hostname);
+/// Whether the arguments are for the `check` subcommand, even when they do not parse.
+fn is_check() -> bool {
+    Opt::command()
+        .ignore_errors(true)
+        .try_get_matches()
+        .is_ok_and(|matches| matches.subcommand_name() == Some("check"))
+}
+
+fn main() -> color_eyre::Result<ExitCode> {
+    let opt = match Opt::try_parse() {
+        Ok(opt) => opt,
+        Err(e) if e.use_stderr() && is_check() => {
+            let _ = e.print();
+            return Ok(ExitCode::from(check::CONFIG_ERROR_EXIT_CODE));
+        }
+        Err(e) => e.exit(),
+    };
+
+    match &opt.command {
+        Some(Command::History(options)) => {
+            history::print_history(options)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Check(options)) => {
+            return Ok(run_check(&opt, options).unwrap_or_else(|e| {
+                eprintln!("Error: {e:?}");
+                ExitCode::from(check::CONFIG_ERROR_EXIT_CODE)
+            }));
+        }
+        None => {}
+    }
+
+    let hostname = validation::Hostname::new(opt.hostname)?;
+    let policy = load_policy(&opt.policy)?;
+    let geoip = GeoIp::load(&opt.geoip_dbs)?;
 
-    println!("Collecting DNS leak test data...");
-    let dns_data = dns_leak::test_dns_leak()?;
+    if opt.output == report::OutputFormat::Tui {
+        // The TUI builds the leak tests on every run, so their options are checked upfront.
+        dns_leak::build_leak_tests(opt.provider, opt.leak_api_url.clone(), opt.ipv6_leak_test)?;
+        let runner = tui::Runner {
+            provider: opt.provider,
+            api_url: opt.leak_api_url,
+            ipv6_leak_test: opt.ipv6_leak_test,
+            hostname,
+            trace_options: opt.trace_options,
+            geoip,
+            save_history: !opt.no_history,
+        };
+        tui::run_tui(runner, policy)?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    println!("Running traceroute [Host: {}]...", hostname);
-    let trace_data = trace::traceroute(hostname.hostname())?;
-    tui::run_tui(dns_data, trace_data)?;
+    eprintln!("Collecting DNS leak test data...");
+    let leak_tests =
+        dns_leak::build_leak_tests(opt.provider, opt.leak_api_url, opt.ipv6_leak_test)?;
+    let mut leak_results = dns_leak::run_leak_tests(&leak_tests);
+    geoip.enrich_reports(leak_results.iter_mut().flatten());
+    let errors: Vec<String> = leak_results
+        .iter()
+        .filter_map(|result| result.as_ref().err().map(ToString::to_string))
+        .collect();
+    if errors.len() == leak_results.len() {
+        color_eyre::eyre::bail!("{}", errors.join("\n"));
+    }
+    // A family that failed is left out of the output, the others are still worth reporting.
+    errors.iter().for_each(|e| eprintln!("warning: {e}"));
+    let leak_reports: Vec<LeakReport> = leak_results.iter().flatten().cloned().collect();
+
+    eprintln!("Running traceroute [Host: {}]...", hostname);
+    let mut traces = trace::traceroute(&hostname, &opt.trace_options)?;
+    traces.iter_mut().for_each(|trace| trace.enrich(&geoip));
+    match opt.output {
+        report::OutputFormat::Tui => unreachable!("the TUI streams the traceroute"),
+        report::OutputFormat::Json => report::print_json(&leak_reports, &traces)?,
+        report::OutputFormat::Csv => report::print_csv(&leak_reports, &traces, &policy),
+        report::OutputFormat::Markdown => report::print_markdown(&leak_reports, &traces, &policy),
+    }
+
+    if !opt.no_history {
+        let run = history::Run::new(&hostname, leak_results, traces, &policy);
+        if let Err(e) = history::append(&run) {
+            eprintln!("warning: {e}");
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn load_policy(path: &Option<PathBuf>) -> color_eyre::Result<Policy> {
+    match path {
+        Some(path) => Policy::load(path),
+        None => Ok(Policy::default()),
+    }
+}
 
-    Ok(())
+/// Sets up the leak tests of `check`, whose errors are all configuration errors, and runs them.
+fn run_check(opt: &Opt, options: &check::CheckOptions) -> color_eyre::Result<ExitCode> {
+    let mut policy = load_policy(&opt.policy)?;
+    options.apply(&mut policy);
+    let geoip = GeoIp::load(&opt.geoip_dbs)?;
+    let leak_tests =
+        dns_leak::build_leak_tests(opt.provider, opt.leak_api_url.clone(), opt.ipv6_leak_test)?;
+    let mut leak_results = dns_leak::run_leak_tests(&leak_tests);
+    geoip.enrich_reports(leak_results.iter_mut().flatten());
+    Ok(check::run_check(leak_results, &policy))
 }
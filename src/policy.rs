@@ -0,0 +1,216 @@
+use crate::dns_leak::{Asn, CountryCode, Endpoint};
+use color_eyre::eyre::WrapErr;
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer};
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+/// Resolvers that are expected to see our DNS queries.
+///
+/// Every non-empty list must match for a resolver to be allowed.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    pub networks: Vec<IpNet>,
+    /// Numbers of the allowed ASNs, written as `AS13335` or `13335` in the file.
+    #[serde(default, deserialize_with = "deserialize_asns")]
+    pub asns: Vec<u32>,
+    #[serde(default)]
+    pub countries: Vec<CountryCode>,
+}
+
+fn deserialize_asns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|text| {
+            text.parse::<Asn>()
+                .map(|asn| asn.number)
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Network,
+    Asn,
+    Country,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Network => write!(f, "network"),
+            Self::Asn => write!(f, "ASN"),
+            Self::Country => write!(f, "country"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyStatus {
+    Allowed,
+    Violating(Vec<Violation>),
+}
+
+impl PolicyStatus {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Self::Allowed)
+    }
+}
+
+impl Display for PolicyStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Allowed => write!(f, "allowed"),
+            Self::Violating(violations) => {
+                let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+                write!(f, "violating ({})", violations.join(", "))
+            }
+        }
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> color_eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read policy {}", path.display()))?;
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("failed to parse policy {}", path.display()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty() && self.asns.is_empty() && self.countries.is_empty()
+    }
+
+    pub fn evaluate(&self, resolver: &Endpoint) -> PolicyStatus {
+        let mut violations = Vec::new();
+        if !self.networks.is_empty() && !self.networks.iter().any(|n| n.contains(&resolver.ip)) {
+            violations.push(Violation::Network);
+        }
+        if !self.asns.is_empty()
+            && !resolver
+                .asn
+                .as_ref()
+                .is_some_and(|asn| self.asns.contains(&asn.number))
+        {
+            violations.push(Violation::Asn);
+        }
+        if !self.countries.is_empty()
+            && !resolver
+                .country
+                .as_ref()
+                .is_some_and(|country| self.countries.contains(country))
+        {
+            violations.push(Violation::Country);
+        }
+
+        if violations.is_empty() {
+            PolicyStatus::Allowed
+        } else {
+            PolicyStatus::Violating(violations)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver(ip: &str, country: &str, asn: &str) -> Endpoint {
+        Endpoint {
+            ip: ip.parse().unwrap(),
+            country: country.parse().ok(),
+            country_name: String::new(),
+            asn: asn.parse().ok(),
+        }
+    }
+
+    fn policy(toml: &str) -> Policy {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn parse_policy() {
+        let policy = policy(
+            r#"
+            networks = ["10.8.0.0/16", "2001:db8::/32"]
+            asns = ["AS9009", "as13335", "15169", "AS64500 Example Net"]
+            countries = ["NL", "de"]
+            "#,
+        );
+        assert_eq!(policy.networks.len(), 2);
+        assert_eq!(policy.asns, [9009, 13335, 15169, 64500]);
+        let countries: Vec<&str> = policy.countries.iter().map(CountryCode::as_str).collect();
+        assert_eq!(countries, ["NL", "DE"]);
+        assert!(Policy::default().is_empty());
+    }
+
+    #[test]
+    fn reject_invalid_policy() {
+        assert!(toml::from_str::<Policy>(r#"asns = ["Cloudflare"]"#).is_err());
+        assert!(toml::from_str::<Policy>(r#"countries = ["Netherlands"]"#).is_err());
+        assert!(toml::from_str::<Policy>(r#"networks = ["10.8.0.0/33"]"#).is_err());
+        assert!(toml::from_str::<Policy>(r#"resolvers = ["10.8.0.1"]"#).is_err());
+    }
+
+    #[test]
+    fn empty_policy_allows_any_resolver() {
+        let status = Policy::default().evaluate(&resolver("192.0.2.1", "", ""));
+        assert_eq!(status, PolicyStatus::Allowed);
+    }
+
+    #[test]
+    fn evaluate_networks() {
+        let policy = policy(r#"networks = ["10.8.0.0/16"]"#);
+        assert!(policy.evaluate(&resolver("10.8.3.4", "", "")).is_allowed());
+        assert_eq!(
+            policy.evaluate(&resolver("10.9.0.1", "", "")),
+            PolicyStatus::Violating(vec![Violation::Network])
+        );
+        assert!(!policy
+            .evaluate(&resolver("2001:db8::1", "", ""))
+            .is_allowed());
+    }
+
+    #[test]
+    fn evaluate_asns() {
+        let policy = policy(r#"asns = ["AS9009"]"#);
+        let allowed = resolver("192.0.2.1", "", "AS9009 M247 Europe SRL");
+        assert!(policy.evaluate(&allowed).is_allowed());
+        assert_eq!(
+            policy.evaluate(&resolver("192.0.2.1", "", "AS90090")),
+            PolicyStatus::Violating(vec![Violation::Asn])
+        );
+        // A resolver of an unknown AS cannot be shown to be allowed.
+        assert!(!policy.evaluate(&resolver("192.0.2.1", "", "")).is_allowed());
+    }
+
+    #[test]
+    fn evaluate_countries() {
+        let policy = policy(r#"countries = ["nl"]"#);
+        assert!(policy
+            .evaluate(&resolver("192.0.2.1", "NL", ""))
+            .is_allowed());
+        assert_eq!(
+            policy.evaluate(&resolver("192.0.2.1", "", "")),
+            PolicyStatus::Violating(vec![Violation::Country])
+        );
+    }
+
+    #[test]
+    fn evaluate_reports_every_violation() {
+        let policy = policy(
+            r#"
+            networks = ["10.8.0.0/16"]
+            asns = ["AS9009"]
+            countries = ["NL"]
+            "#,
+        );
+        assert_eq!(
+            policy.evaluate(&resolver("192.0.2.1", "DE", "AS13335")),
+            PolicyStatus::Violating(vec![Violation::Network, Violation::Asn, Violation::Country])
+        );
+    }
+}
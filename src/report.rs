@@ -0,0 +1,184 @@
+use crate::{
+    dns_leak::LeakReport,
+    policy::Policy,
+    table::{self, TableModel},
+    trace::TraceData,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Interactive terminal interface.
+    Tui,
+    /// A single JSON document on stdout.
+    Json,
+    /// The DNS and traceroute tables as CSV.
+    Csv,
+    /// The DNS and traceroute tables as Markdown.
+    Markdown,
+}
+
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub dns_leak: &'a [LeakReport],
+    pub traceroutes: &'a [TraceData],
+}
+
+pub fn print_json(leak_reports: &[LeakReport], traces: &[TraceData]) -> color_eyre::Result<()> {
+    let report = Report {
+        dns_leak: leak_reports,
+        traceroutes: traces,
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+pub fn print_csv(leak_reports: &[LeakReport], traces: &[TraceData], policy: &Policy) {
+    print!("{}", csv(leak_reports, traces, policy));
+}
+
+/// Every table as CSV, one after the other with an empty line in between.
+fn csv(leak_reports: &[LeakReport], traces: &[TraceData], policy: &Policy) -> String {
+    let tables: Vec<TableModel> = leak_reports
+        .iter()
+        .map(|leak_report| table::dns_table(leak_report, policy))
+        .chain(traces.iter().map(table::trace_table))
+        .collect();
+    tables
+        .iter()
+        .map(csv_table)
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+pub fn print_markdown(leak_reports: &[LeakReport], traces: &[TraceData], policy: &Policy) {
+    let output = leak_reports
+        .iter()
+        .flat_map(|leak_report| {
+            [
+                markdown_table(&table::dns_table(leak_report, policy)),
+                format!("**{}**\n", leak_report.conclusion),
+            ]
+        })
+        .chain(
+            traces
+                .iter()
+                .map(|trace_data| markdown_table(&table::trace_table(trace_data))),
+        )
+        .collect::<Vec<_>>()
+        .join("\n");
+    print!("{output}");
+}
+
+fn csv_table(table: &TableModel) -> String {
+    let mut output = csv_row([table.title.as_str()]);
+    output.push_str(&csv_row(table.headers.iter().copied()));
+    for row in &table.rows {
+        output.push_str(&csv_row(row.iter().map(String::as_str)));
+    }
+    output
+}
+
+fn csv_row<'a>(fields: impl IntoIterator<Item = &'a str>) -> String {
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+fn markdown_table(table: &TableModel) -> String {
+    let mut output = format!("## {}\n\n", table.title);
+    output.push_str(&markdown_row(table.headers.iter().copied()));
+    output.push_str(&markdown_row(table.headers.iter().map(|_| "---")));
+    for row in &table.rows {
+        output.push_str(&markdown_row(row.iter().map(String::as_str)));
+    }
+    output
+}
+
+fn markdown_row<'a>(cells: impl IntoIterator<Item = &'a str>) -> String {
+    let cells: Vec<String> = cells
+        .into_iter()
+        .map(|cell| cell.replace('|', "\\|").replace('\n', " "))
+        .collect();
+    format!("| {} |\n", cells.join(" | "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_leak::{Endpoint, IpFamily};
+
+    fn resolver(ip: &str, asn: &str) -> Endpoint {
+        Endpoint {
+            ip: ip.parse().unwrap(),
+            country: None,
+            country_name: String::from("Netherlands"),
+            asn: asn.parse().ok(),
+        }
+    }
+
+    #[test]
+    fn csv_row_quotes_special_fields() {
+        assert_eq!(csv_row(["a", "b c", ""]), "a,b c,\r\n");
+        assert_eq!(csv_row(["Cloudflare, Inc."]), "\"Cloudflare, Inc.\"\r\n");
+        assert_eq!(csv_row(["say \"hi\""]), "\"say \"\"hi\"\"\"\r\n");
+        assert_eq!(csv_row(["two\nlines"]), "\"two\nlines\"\r\n");
+        assert_eq!(csv_row(["cr\r"]), "\"cr\r\"\r\n");
+    }
+
+    #[test]
+    fn csv_separates_tables_with_an_empty_line() {
+        let ipv4 = LeakReport {
+            family: Some(IpFamily::Ipv4),
+            resolvers: vec![resolver("192.0.2.53", "AS13335 Cloudflare, Inc.")],
+            ..Default::default()
+        };
+        let ipv6 = LeakReport {
+            family: Some(IpFamily::Ipv6),
+            resolvers: vec![resolver("2001:db8::53", "")],
+            ..Default::default()
+        };
+        let output = csv(&[ipv4, ipv6], &[], &Policy::default());
+        assert_eq!(
+            output,
+            "DNS Leak Test (IPv4)\r\n\
+             IP,Country,ASN\r\n\
+             192.0.2.53,Netherlands ?,\"AS13335 Cloudflare, Inc.\"\r\n\
+             \r\n\
+             DNS Leak Test (IPv6)\r\n\
+             IP,Country,ASN\r\n\
+             2001:db8::53,Netherlands ?,\r\n"
+        );
+    }
+
+    #[test]
+    fn csv_appends_the_trace_table() {
+        let json = r#"{"summary": "Traceroute", "hops": [{"ttl": 1, "host": "gateway",
+            "address": "192.0.2.1", "rtts_ms": [1.0, null], "stats": {"sent": 2,
+            "received": 1, "loss_pct": 50.0, "best_ms": 1.0, "avg_ms": 1.0,
+            "worst_ms": 1.0, "stddev_ms": 0.0, "jitter_ms": null}}]}"#;
+        let trace_data: TraceData = serde_json::from_str(json).unwrap();
+        let report = LeakReport {
+            family: Some(IpFamily::Ipv4),
+            resolvers: vec![resolver("192.0.2.53", "")],
+            ..Default::default()
+        };
+        let output = csv(&[report], &[trace_data], &Policy::default());
+        assert!(output.ends_with(
+            "192.0.2.53,Netherlands ?,\r\n\
+             \r\n\
+             Traceroute\r\n\
+             TTL,Host,Address,Loss,Snt,Rcv,Best,Avg,Wrst,StDev,Jttr\r\n\
+             1,gateway,192.0.2.1,50.0%,2,1,1.0,1.0,1.0,0.0,\r\n"
+        ));
+    }
+}
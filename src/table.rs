@@ -0,0 +1,136 @@
+use crate::{
+    dns_leak::{Asn, CountryCode, LeakReport},
+    policy::Policy,
+    trace::TraceData,
+};
+
+pub struct TableModel {
+    pub title: String,
+    pub headers: Vec<&'static str>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Builds the resolver table, with a trailing status column when `policy` is not empty.
+pub fn dns_table(report: &LeakReport, policy: &Policy) -> TableModel {
+    let mut headers = vec!["IP", "Country", "ASN"];
+    if !policy.is_empty() {
+        headers.push("Status");
+    }
+    TableModel {
+        title: match report.family {
+            Some(family) => format!("DNS Leak Test ({family})"),
+            None => String::from("DNS Leak Test"),
+        },
+        headers,
+        rows: report
+            .resolvers
+            .iter()
+            .map(|resolver| {
+                let mut row = vec![
+                    resolver.ip.to_string(),
+                    format!("{} {}", resolver.country_name, resolver.flag()),
+                    resolver.asn_label(),
+                ];
+                if !policy.is_empty() {
+                    row.push(policy.evaluate(resolver).to_string());
+                }
+                row
+            })
+            .collect(),
+    }
+}
+
+/// Builds the hop table, with ASN and country columns when the hops were enriched.
+pub fn trace_table(trace_data: &TraceData) -> TableModel {
+    let mut rows = Vec::new();
+    trace_data.hops(|hop| {
+        let mut row = vec![
+            hop.ttl().map(|ttl| ttl.to_string()).unwrap_or_default(),
+            hop.host(),
+            hop.address(),
+        ];
+        if trace_data.with_as_info() || trace_data.with_geo() {
+            let asn = hop
+                .as_info()
+                .map(|info| format!("{} {}", info.asn, info.name))
+                .or_else(|| {
+                    hop.geo()
+                        .and_then(|geo| geo.asn.as_ref().map(Asn::to_string))
+                });
+            row.push(asn.unwrap_or_default());
+        }
+        if trace_data.with_geo() {
+            row.push(
+                hop.geo()
+                    .and_then(|geo| geo.country.as_ref().map(CountryCode::to_string))
+                    .unwrap_or_default(),
+            );
+        }
+        // Additional addresses of a TTL share the statistics of its first row.
+        if hop.ttl().is_some() {
+            let stats = hop.stats();
+            row.extend([
+                format!("{:.1}%", stats.loss_pct),
+                stats.sent.to_string(),
+                stats.received.to_string(),
+                millis(stats.best_ms),
+                millis(stats.avg_ms),
+                millis(stats.worst_ms),
+                millis(stats.stddev_ms),
+                millis(stats.jitter_ms),
+            ]);
+        }
+        rows.push(row);
+    });
+    let mut headers = vec!["TTL", "Host", "Address"];
+    if trace_data.with_as_info() || trace_data.with_geo() {
+        headers.push("ASN");
+    }
+    if trace_data.with_geo() {
+        headers.push("Country");
+    }
+    headers.extend(["Loss", "Snt", "Rcv", "Best", "Avg", "Wrst", "StDev", "Jttr"]);
+    for row in &mut rows {
+        row.resize(headers.len(), String::new());
+    }
+    TableModel {
+        title: trace_data.summary().to_string(),
+        headers,
+        rows,
+    }
+}
+
+fn millis(value: Option<f64>) -> String {
+    value.map(|ms| format!("{ms:.1}")).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn additional_addresses_fill_every_column() {
+        let stats = r#"{"sent": 3, "received": 3, "loss_pct": 0.0, "best_ms": 1.0,
+            "avg_ms": 1.0, "worst_ms": 1.0, "stddev_ms": 0.0, "jitter_ms": 0.0}"#;
+        let hop = |ttl: &str, address: &str| {
+            format!(
+                r#"{{"ttl": {ttl}, "host": "{address}", "address": "{address}",
+                    "rtts_ms": [1.0, 1.0, 1.0], "stats": {stats}}}"#
+            )
+        };
+        let json = format!(
+            r#"{{"summary": "Traceroute", "hops": [{}, {}, {}]}}"#,
+            hop("1", "192.0.2.1"),
+            hop("null", "192.0.2.2"),
+            hop("2", "198.51.100.1"),
+        );
+        let trace_data: TraceData = serde_json::from_str(&json).unwrap();
+        let table = trace_table(&trace_data);
+        assert_eq!(table.rows.len(), 3);
+        for row in &table.rows {
+            assert_eq!(row.len(), table.headers.len());
+        }
+        assert_eq!(table.rows[1][1], "192.0.2.2");
+        assert!(table.rows[1][3..].iter().all(String::is_empty));
+    }
+}
@@ -1,11 +1,31 @@
-use itertools::Itertools;
+use crate::geoip::{GeoInfo, GeoIp};
+use crate::validation::Hostname;
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
-use trippy::core::{Builder, PortDirection, Protocol};
-use trippy::dns::{Config, DnsResolver, Resolver};
+use trippy::core::{Builder, PortDirection, ProbeStatus, Protocol, Round, Tracer};
+use trippy::dns::{
+    Builder as DnsConfigBuilder, DnsEntry, DnsResolver, IpAddrFamily, ResolveMethod, Resolved,
+    Resolver, Unresolved,
+};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TraceData {
     summary: String,
     hops: Vec<Hop>,
+    #[serde(skip)]
+    with_as_info: bool,
+    #[serde(skip)]
+    with_geo: bool,
 }
 
 impl TraceData {
@@ -13,6 +33,31 @@ impl TraceData {
         &self.summary
     }
 
+    /// Whether the AS of every hop was looked up.
+    pub fn with_as_info(&self) -> bool {
+        // Traces read back from the history only know which hops have an AS.
+        self.with_as_info || self.hops.iter().any(|hop| hop.as_info.is_some())
+    }
+
+    /// Whether every hop was looked up in the GeoIP databases.
+    pub fn with_geo(&self) -> bool {
+        self.with_geo || self.hops.iter().any(|hop| hop.geo.is_some())
+    }
+
+    pub fn enrich(&mut self, geoip: &GeoIp) {
+        if geoip.is_empty() {
+            return;
+        }
+        for hop in &mut self.hops {
+            hop.geo = hop
+                .address
+                .as_deref()
+                .and_then(|address| address.parse().ok())
+                .and_then(|ip| geoip.lookup(ip));
+        }
+        self.with_geo = true;
+    }
+
     pub fn hops<F>(&self, mut f: F)
     where
         F: FnMut(&Hop),
@@ -21,19 +66,114 @@ impl TraceData {
             f(hop);
         }
     }
+
+    /// Number of TTLs that were probed.
+    pub fn hop_count(&self) -> usize {
+        self.hops.iter().filter(|hop| hop.ttl.is_some()).count()
+    }
+
+    /// Returns the rows of the TTL that the `index`th row belongs to, one per address.
+    pub fn ttl_hops(&self, index: usize) -> &[Hop] {
+        let Some(rows) = self.hops.get(..=index) else {
+            return &[];
+        };
+        let start = rows.iter().rposition(|hop| hop.ttl.is_some()).unwrap_or(0);
+        let len = 1 + self.hops[start + 1..]
+            .iter()
+            .take_while(|hop| hop.ttl.is_none())
+            .count();
+        &self.hops[start..start + len]
+    }
+
+    /// Combines this trace with one over later rounds to the same address.
+    fn merge(&self, later: TraceData) -> TraceData {
+        let mut groups = ttl_groups(self.hops.clone());
+        for (ttl, rows) in ttl_groups(later.hops) {
+            let rows = match groups.remove(&ttl) {
+                Some(earlier) => merge_ttl(ttl, earlier, rows),
+                None => rows,
+            };
+            groups.insert(ttl, rows);
+        }
+        TraceData {
+            summary: later.summary,
+            hops: groups.into_values().flatten().collect(),
+            with_as_info: later.with_as_info,
+            with_geo: later.with_geo,
+        }
+    }
+}
+
+/// Groups the rows of a trace by TTL, with the additional addresses after the first one.
+fn ttl_groups(hops: Vec<Hop>) -> BTreeMap<u8, Vec<Hop>> {
+    let mut groups: BTreeMap<u8, Vec<Hop>> = BTreeMap::new();
+    let mut current = None;
+    for hop in hops {
+        current = hop.ttl.or(current);
+        if let Some(ttl) = current {
+            groups.entry(ttl).or_default().push(hop);
+        }
+    }
+    groups
+}
+
+/// Merges the rows of a TTL, keeping the addresses seen in either and the newest host names.
+fn merge_ttl(ttl: u8, earlier: Vec<Hop>, later: Vec<Hop>) -> Vec<Hop> {
+    let stats = earlier[0].stats.merge(&later[0].stats);
+    let mut samples = earlier[0].samples.clone();
+    samples.extend_from_slice(&later[0].samples);
+    samples.drain(..samples.len().saturating_sub(MAX_SAMPLES));
+
+    let mut rows: Vec<Hop> = earlier
+        .into_iter()
+        .filter(|hop| hop.address.is_some())
+        .collect();
+    for hop in later.into_iter().filter(|hop| hop.address.is_some()) {
+        match rows.iter_mut().find(|row| row.address == hop.address) {
+            Some(row) => *row = hop,
+            None => rows.push(hop),
+        }
+    }
+    if rows.is_empty() {
+        rows.push(Hop {
+            ttl: None,
+            host: None,
+            address: None,
+            as_info: None,
+            geo: None,
+            samples: Vec::new(),
+            stats,
+        });
+    }
+    for (i, row) in rows.iter_mut().enumerate() {
+        row.ttl = (i == 0).then_some(ttl);
+        row.samples = samples.clone();
+        row.stats = stats;
+    }
+    rows
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Hop {
-    ttl: Option<String>,
+    ttl: Option<u8>,
     host: Option<String>,
     address: Option<String>,
-    samples: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    as_info: Option<AsInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    geo: Option<GeoInfo>,
+    #[serde(
+        rename = "rtts_ms",
+        serialize_with = "serialize_millis",
+        deserialize_with = "deserialize_millis"
+    )]
+    samples: Vec<Option<Duration>>,
+    stats: HopStats,
 }
 
 impl Hop {
-    pub fn ttl(&self) -> Option<String> {
-        self.ttl.as_deref().unwrap_or_default().parse().ok()
+    pub fn ttl(&self) -> Option<u8> {
+        self.ttl
     }
 
     pub fn host(&self) -> String {
@@ -44,57 +184,724 @@ impl Hop {
         self.address.as_deref().unwrap_or("*").to_string()
     }
 
-    pub fn samples(&self) -> String {
-        self.samples.to_string()
+    pub fn as_info(&self) -> Option<&AsInfo> {
+        self.as_info.as_ref()
+    }
+
+    pub fn geo(&self) -> Option<&GeoInfo> {
+        self.geo.as_ref()
+    }
+
+    /// Round-trip time of every round, oldest first, or `None` where the probe was lost.
+    pub fn samples(&self) -> &[Option<Duration>] {
+        &self.samples
+    }
+
+    pub fn stats(&self) -> &HopStats {
+        &self.stats
+    }
+}
+
+/// The autonomous system that announces a hop address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AsInfo {
+    pub asn: String,
+    pub prefix: String,
+    pub name: String,
+}
+
+impl AsInfo {
+    /// Returns `None` for addresses without an AS, such as private ones.
+    fn new(info: trippy::dns::AsInfo) -> Option<Self> {
+        (!info.asn.is_empty()).then(|| Self {
+            asn: format!("AS{}", info.asn),
+            prefix: info.prefix,
+            name: info.name,
+        })
+    }
+}
+
+/// Probe statistics of a single TTL.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct HopStats {
+    pub sent: usize,
+    pub received: usize,
+    pub loss_pct: f64,
+    pub best_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub worst_ms: Option<f64>,
+    pub stddev_ms: Option<f64>,
+    /// Average difference between consecutive round-trip times.
+    pub jitter_ms: Option<f64>,
+}
+
+impl HopStats {
+    /// Combines the statistics of two runs over the same TTL, like trippy would have for one.
+    fn merge(&self, later: &Self) -> Self {
+        let sent = self.sent + later.sent;
+        let received = self.received + later.received;
+        let (n1, n2, n) = (self.received as f64, later.received as f64, received as f64);
+        let weighted = |a: Option<f64>, b: Option<f64>| match (a, b) {
+            (Some(a), Some(b)) => Some((a * n1 + b * n2) / n),
+            (a, b) => a.or(b),
+        };
+        let avg_ms = weighted(self.avg_ms, later.avg_ms);
+        // Chan et al.'s formula for the sum of squared differences of both runs.
+        let m2 = |stats: &Self| {
+            stats
+                .stddev_ms
+                .map_or(0_f64, |sd| sd * sd * (stats.received as f64 - 1_f64))
+        };
+        let stddev_ms = match (self.avg_ms, later.avg_ms) {
+            (Some(a), Some(b)) => {
+                let delta = b - a;
+                Some(((m2(self) + m2(later) + delta * delta * n1 * n2 / n) / (n - 1_f64)).sqrt())
+            }
+            _ => self.stddev_ms.or(later.stddev_ms),
+        }
+        .filter(|_| received > 1);
+        Self {
+            sent,
+            received,
+            loss_pct: if sent > 0 {
+                (sent - received) as f64 / sent as f64 * 100_f64
+            } else {
+                0_f64
+            },
+            best_ms: min_max(self.best_ms, later.best_ms, f64::min),
+            avg_ms,
+            worst_ms: min_max(self.worst_ms, later.worst_ms, f64::max),
+            stddev_ms,
+            jitter_ms: weighted(self.jitter_ms, later.jitter_ms).filter(|_| received > 1),
+        }
+    }
+}
+
+fn min_max(a: Option<f64>, b: Option<f64>, pick: fn(f64, f64) -> f64) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(pick(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+impl From<&trippy::core::Hop> for HopStats {
+    fn from(hop: &trippy::core::Hop) -> Self {
+        let received = hop.total_recv() > 0;
+        Self {
+            sent: hop.total_sent(),
+            received: hop.total_recv(),
+            loss_pct: hop.loss_pct(),
+            best_ms: hop.best_ms(),
+            avg_ms: received.then(|| hop.avg_ms()),
+            worst_ms: hop.worst_ms(),
+            stddev_ms: (hop.total_recv() > 1).then(|| hop.stddev_ms()),
+            jitter_ms: (hop.total_recv() > 1).then(|| hop.javg_ms()),
+        }
+    }
+}
+
+/// Writes the round-trip times in milliseconds, `null` for the rounds whose probe was lost.
+fn serialize_millis<S: Serializer>(
+    samples: &[Option<Duration>],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        samples
+            .iter()
+            .map(|s| s.map(|s| s.as_secs_f64() * 1000_f64)),
+    )
+}
+
+fn deserialize_millis<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Option<Duration>>, D::Error> {
+    let millis = Vec::<Option<f64>>::deserialize(deserializer)?;
+    millis
+        .into_iter()
+        .map(|ms| {
+            ms.map(|ms| Duration::try_from_secs_f64(ms / 1000_f64))
+                .transpose()
+                .map_err(|e| serde::de::Error::custom(format!("invalid RTT {ms:?} ms: {e}")))
+        })
+        .collect()
+}
+
+/// Samples kept per hop across the batches of a continuous trace, as many as trippy keeps.
+const MAX_SAMPLES: usize = 256;
+
+/// Rounds per tracer of a continuous trace, which bounds how long a stopped one keeps probing.
+const CONTINUOUS_BATCH_ROUNDS: usize = 10;
+
+/// The sequence number trippy starts at by default.
+const DEFAULT_INITIAL_SEQUENCE: u16 = 33434;
+
+/// The largest sequence number trippy accepts to start at.
+const MAX_INITIAL_SEQUENCE: u16 = u16::MAX - 2 * 1024;
+
+const DEFAULT_UDP_PORT: u16 = 33434;
+const DEFAULT_TCP_PORT: u16 = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TraceProtocol {
+    Icmp,
+    Udp,
+    Tcp,
+}
+
+impl TraceProtocol {
+    fn dest_port(self, port: Option<u16>) -> Option<u16> {
+        match self {
+            Self::Icmp => None,
+            Self::Udp => port,
+            Self::Tcp => Some(port.unwrap_or(DEFAULT_TCP_PORT)),
+        }
+    }
+
+    fn port_direction(self, port: Option<u16>) -> PortDirection {
+        match (self, self.dest_port(port)) {
+            (Self::Icmp, _) => PortDirection::None,
+            (_, Some(port)) => PortDirection::new_fixed_dest(port),
+            (_, None) => PortDirection::new_fixed_src(DEFAULT_UDP_PORT),
+        }
+    }
+}
+
+impl From<TraceProtocol> for Protocol {
+    fn from(protocol: TraceProtocol) -> Self {
+        match protocol {
+            TraceProtocol::Icmp => Self::Icmp,
+            TraceProtocol::Udp => Self::Udp,
+            TraceProtocol::Tcp => Self::Tcp,
+        }
+    }
+}
+
+impl Display for TraceProtocol {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Icmp => write!(f, "ICMP"),
+            Self::Udp => write!(f, "UDP"),
+            Self::Tcp => write!(f, "TCP"),
+        }
+    }
+}
+
+/// How hostnames and hop addresses are resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DnsResolveMethod {
+    System,
+    Resolv,
+    Google,
+    Cloudflare,
+}
+
+impl From<DnsResolveMethod> for ResolveMethod {
+    fn from(method: DnsResolveMethod) -> Self {
+        match method {
+            DnsResolveMethod::System => Self::System,
+            DnsResolveMethod::Resolv => Self::Resolv,
+            DnsResolveMethod::Google => Self::Google,
+            DnsResolveMethod::Cloudflare => Self::Cloudflare,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct TraceOptions {
+    /// Protocol to trace with.
+    #[clap(long = "protocol", value_enum, default_value_t = TraceProtocol::Udp)]
+    pub protocol: TraceProtocol,
+
+    /// Destination port, overriding the one given with the hostname for TCP.
+    #[clap(long = "port", value_name = "PORT")]
+    pub port: Option<u16>,
+
+    #[clap(long = "first-ttl", default_value_t = 1, value_name = "TTL")]
+    pub first_ttl: u8,
+
+    #[clap(long = "max-ttl", default_value_t = 64, value_name = "TTL")]
+    pub max_ttl: u8,
+
+    /// Number of probes sent to each hop.
+    #[clap(long = "queries", default_value_t = 3, value_name = "COUNT")]
+    pub queries: usize,
+
+    #[clap(long = "tos", default_value_t = 0, value_name = "TOS")]
+    pub tos: u8,
+
+    #[clap(long = "packet-size", default_value_t = 52, value_name = "BYTES")]
+    pub packet_size: u16,
+
+    /// Pause between rounds in milliseconds.
+    #[clap(long = "interval", default_value_t = 100, value_name = "MS")]
+    pub interval: u64,
+
+    #[clap(long = "interface", value_name = "NAME")]
+    pub interface: Option<String>,
+
+    #[clap(long = "source", value_name = "IP")]
+    pub source: Option<IpAddr>,
+
+    /// Only trace IPv4 addresses.
+    #[clap(short = '4', long = "ipv4", conflicts_with = "ipv6")]
+    pub ipv4: bool,
+
+    /// Only trace IPv6 addresses.
+    #[clap(short = '6', long = "ipv6")]
+    pub ipv6: bool,
+
+    /// Trace every resolved address instead of the first one.
+    #[clap(long = "all-addresses")]
+    pub all_addresses: bool,
+
+    /// Keep tracing in the TUI until paused instead of stopping after --queries rounds.
+    #[clap(long = "continuous")]
+    pub continuous: bool,
+
+    #[clap(
+        long = "dns-resolve-method",
+        value_enum,
+        default_value_t = DnsResolveMethod::System
+    )]
+    pub dns_resolve_method: DnsResolveMethod,
+
+    /// Look up the AS of every hop (needs a --dns-resolve-method other than system).
+    #[clap(long = "dns-lookup-as-info")]
+    pub dns_lookup_as_info: bool,
+}
+
+impl TraceOptions {
+    fn addr_family(&self) -> IpAddrFamily {
+        if self.ipv4 {
+            IpAddrFamily::Ipv4Only
+        } else if self.ipv6 {
+            IpAddrFamily::Ipv6Only
+        } else {
+            IpAddrFamily::Ipv4thenIpv6
+        }
+    }
+
+    /// The families to look the hostname up in.
+    ///
+    /// trippy only returns the preferred family of a dual-stack host, so `--all-addresses`
+    /// looks up each family on its own.
+    fn lookup_families(&self) -> Vec<IpAddrFamily> {
+        if self.all_addresses && !self.ipv4 && !self.ipv6 {
+            vec![IpAddrFamily::Ipv4Only, IpAddrFamily::Ipv6Only]
+        } else {
+            vec![self.addr_family()]
+        }
+    }
+
+    fn allows(&self, addr: &IpAddr) -> bool {
+        match addr {
+            IpAddr::V4(_) => !self.ipv6,
+            IpAddr::V6(_) => !self.ipv4,
+        }
+    }
+}
+
+/// Lets the TUI pause a streaming trace and abandon it.
+#[derive(Clone, Default)]
+pub struct TraceControl {
+    paused: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    /// The threads still tracing.
+    workers: Arc<AtomicUsize>,
+}
+
+impl TraceControl {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle_pause(&self) {
+        self.paused.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Whether all threads of the trace have returned.
+    pub fn is_finished(&self) -> bool {
+        self.workers.load(Ordering::Relaxed) == 0
+    }
+
+    fn worker(&self) -> Worker {
+        self.workers.fetch_add(1, Ordering::Relaxed);
+        Worker(self.workers.clone())
+    }
+
+    /// Blocks the calling tracer while it is paused, unless it was stopped.
+    fn wait(&self) {
+        while self.is_paused() && !self.is_stopped() {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Counts a thread of a [`TraceControl`] as tracing until it is dropped.
+struct Worker(Arc<AtomicUsize>);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The latest state of the `index`th traced address.
+pub struct TraceUpdate {
+    pub index: usize,
+    /// Set when the hostname has more addresses than are traced without `--all-addresses`.
+    pub truncated: bool,
+    /// Set on the last update of the trace, or of each batch of rounds when it is continuous.
+    pub complete: bool,
+    pub result: Result<TraceData, String>,
+}
+
+fn start_resolver(options: &TraceOptions) -> color_eyre::Result<DnsResolver> {
+    start_family_resolver(options, options.addr_family())
+}
+
+fn start_family_resolver(
+    options: &TraceOptions,
+    addr_family: IpAddrFamily,
+) -> color_eyre::Result<DnsResolver> {
+    if options.dns_lookup_as_info && options.dns_resolve_method == DnsResolveMethod::System {
+        color_eyre::eyre::bail!("AS lookups are not supported with the system resolver");
+    }
+    let config = DnsConfigBuilder::new()
+        .resolve_method(options.dns_resolve_method.into())
+        .addr_family(addr_family)
+        .build();
+    Ok(DnsResolver::start(config)?)
+}
+
+/// Splits a reverse lookup into the host names and the AS info, if it was looked up.
+fn reverse_dns(entry: DnsEntry) -> (String, Option<AsInfo>) {
+    match entry {
+        DnsEntry::Resolved(Resolved::WithAsInfo(_, hosts, info), _) => {
+            (hosts.join(" "), AsInfo::new(info))
+        }
+        DnsEntry::NotFound(Unresolved::WithAsInfo(addr, info), _) => {
+            (addr.to_string(), AsInfo::new(info))
+        }
+        entry => (entry.to_string(), None),
     }
 }
 
-pub fn traceroute(hostname: &str) -> color_eyre::Result<TraceData> {
-    let interface = None::<String>;
-    let src_addr = None;
-    let port = 33434;
-    let first_ttl = 1;
-    let max_ttl = 64;
-    let nqueries = 3;
-    let tos = 0;
-    let pausemecs = 100;
-    let port_direction = PortDirection::new_fixed_src(port);
-    let resolver = DnsResolver::start(Config::default())?;
-    let addrs: Vec<_> = resolver
-        .lookup(hostname)
-        .map_err(|_| color_eyre::eyre::eyre!(format!("traceroute: unknown host {}", hostname)))?
+/// Resolves the addresses of `hostname` that should be traced.
+///
+/// The returned flag is set when some addresses were dropped because `--all-addresses` is off.
+fn resolve_addrs(
+    hostname: &Hostname,
+    options: &TraceOptions,
+) -> color_eyre::Result<(Vec<IpAddr>, bool)> {
+    let addrs: Vec<_> = match hostname.ip() {
+        Some(ip) => vec![ip],
+        None => {
+            let lookups = options
+                .lookup_families()
+                .into_iter()
+                .map(|family| {
+                    Ok(start_family_resolver(options, family)?.lookup(hostname.hostname()))
+                })
+                .collect::<color_eyre::Result<Vec<_>>>()?;
+            // A family without addresses is fine as long as the other one has some.
+            if lookups.iter().all(Result::is_err) {
+                color_eyre::eyre::bail!("traceroute: unknown host {hostname}");
+            }
+            lookups.into_iter().flatten().flatten().collect()
+        }
+    };
+    let mut addrs: Vec<_> = addrs
         .into_iter()
+        .filter(|addr| options.allows(addr))
         .collect();
-    let addr = match addrs.as_slice() {
-        [] => {
-            return Err(color_eyre::eyre::eyre!(
-                "traceroute: unknown host {}",
-                hostname
-            ))
-        }
-        [addr] => *addr,
-        [addr, ..] => {
-            println!("traceroute: Warning: {hostname} has multiple addresses; using {addr}");
-            *addr
+    match addrs.as_slice() {
+        [] if options.ipv4 || options.ipv6 => Err(color_eyre::eyre::eyre!(
+            "traceroute: {hostname} has no {} address",
+            if options.ipv4 { "IPv4" } else { "IPv6" }
+        )),
+        [] => Err(color_eyre::eyre::eyre!(
+            "traceroute: unknown host {}",
+            hostname
+        )),
+        [_, _, ..] if !options.all_addresses => {
+            addrs.truncate(1);
+            Ok((addrs, true))
+        }
+        _ => Ok((addrs, false)),
+    }
+}
+
+/// Runs a traceroute to the first address of `hostname`, or to all of them with `--all-addresses`.
+pub fn traceroute(
+    hostname: &Hostname,
+    options: &TraceOptions,
+) -> color_eyre::Result<Vec<TraceData>> {
+    let resolver = start_resolver(options)?;
+    let (addrs, truncated) = resolve_addrs(hostname, options)?;
+    if truncated {
+        eprintln!(
+            "traceroute: Warning: {hostname} has multiple addresses; using {}",
+            addrs[0]
+        );
+    }
+    addrs
+        .into_iter()
+        .enumerate()
+        .map(|(index, addr)| {
+            let (tracer, summary) = build_tracer(
+                hostname,
+                addr,
+                options,
+                Some(options.queries),
+                index,
+                DEFAULT_INITIAL_SEQUENCE,
+            )?;
+            tracer.run()?;
+            trace_data(
+                &tracer,
+                &resolver,
+                summary,
+                false,
+                options.dns_lookup_as_info,
+            )
+        })
+        .collect()
+}
+
+/// Runs the traceroute on background threads, sending a [`TraceUpdate`] after every round.
+///
+/// The trace starts once the threads of the `previous` one, which should be stopped, are done.
+pub fn spawn_traceroute(
+    hostname: Hostname,
+    options: TraceOptions,
+    updates: Sender<TraceUpdate>,
+    previous: &TraceControl,
+) -> TraceControl {
+    let control = TraceControl::default();
+    let worker_control = control.clone();
+    let previous = previous.clone();
+    let worker = control.worker();
+    thread::spawn(move || {
+        let _worker = worker;
+        // Two tracers to the same address would take each other's replies.
+        while !previous.is_finished() {
+            thread::sleep(Duration::from_millis(100));
         }
+        if worker_control.is_stopped() {
+            return;
+        }
+        let addrs = resolve_addrs(&hostname, &options);
+        match addrs {
+            Ok((addrs, truncated)) => {
+                for (index, addr) in addrs.into_iter().enumerate() {
+                    let hostname = hostname.clone();
+                    let options = options.clone();
+                    let control = worker_control.clone();
+                    let updates = updates.clone();
+                    let worker = control.worker();
+                    thread::spawn(move || {
+                        let _worker = worker;
+                        let result = stream_addr(
+                            &hostname, addr, &options, &control, &updates, index, truncated,
+                        );
+                        if let Err(e) = result {
+                            let _ = updates.send(TraceUpdate {
+                                index,
+                                truncated,
+                                complete: true,
+                                result: Err(e.to_string()),
+                            });
+                        }
+                    });
+                }
+            }
+            Err(e) => {
+                let _ = updates.send(TraceUpdate {
+                    index: 0,
+                    truncated: false,
+                    complete: true,
+                    result: Err(e.to_string()),
+                });
+            }
+        }
+    });
+    control
+}
+
+fn stream_addr(
+    hostname: &Hostname,
+    addr: IpAddr,
+    options: &TraceOptions,
+    control: &TraceControl,
+    updates: &Sender<TraceUpdate>,
+    index: usize,
+    truncated: bool,
+) -> color_eyre::Result<()> {
+    let resolver = start_resolver(options)?;
+    // trippy cannot cancel a trace, so a continuous one runs in batches of rounds that are
+    // merged into one and a stopped one ends with its batch.
+    let max_rounds = if options.continuous {
+        CONTINUOUS_BATCH_ROUNDS
+    } else {
+        options.queries
+    };
+    let mut earlier: Option<TraceData> = None;
+    // Each batch starts past the probes of the previous one, whose late replies would
+    // otherwise be taken for replies to the new probes.
+    let next_sequence = Cell::new(DEFAULT_INITIAL_SEQUENCE);
+    loop {
+        let (tracer, summary) = build_tracer(
+            hostname,
+            addr,
+            options,
+            Some(max_rounds),
+            index,
+            next_sequence.get(),
+        )?;
+        let snapshot = |lazy| {
+            let data = trace_data(
+                &tracer,
+                &resolver,
+                summary.clone(),
+                lazy,
+                options.dns_lookup_as_info,
+            )?;
+            Ok::<_, color_eyre::Report>(match &earlier {
+                Some(earlier) => earlier.merge(data),
+                None => data,
+            })
+        };
+        tracer.run_with(|round| {
+            if let Some(sequence) = last_sequence(round) {
+                let next = match sequence {
+                    ..MAX_INITIAL_SEQUENCE => sequence + 1,
+                    _ => DEFAULT_INITIAL_SEQUENCE,
+                };
+                next_sequence.set(next);
+            }
+            if control.is_stopped() {
+                return;
+            }
+            let result = snapshot(true).map_err(|e| e.to_string());
+            let update = TraceUpdate {
+                index,
+                truncated,
+                complete: false,
+                result,
+            };
+            if updates.send(update).is_err() {
+                control.stop();
+            }
+            control.wait();
+        })?;
+        if control.is_stopped() {
+            return Ok(());
+        }
+        // Only the final snapshot resolves the hostnames that are still missing.
+        let data = snapshot(options.continuous)?;
+        let _ = updates.send(TraceUpdate {
+            index,
+            truncated,
+            complete: true,
+            result: Ok(data.clone()),
+        });
+        if !options.continuous {
+            return Ok(());
+        }
+        earlier = Some(data);
+    }
+}
+
+/// The sequence number of the last probe sent in `round`.
+fn last_sequence(round: &Round<'_>) -> Option<u16> {
+    round
+        .probes
+        .iter()
+        .rev()
+        .find_map(|probe| match probe {
+            ProbeStatus::Awaited(probe) => Some(probe.sequence),
+            ProbeStatus::Complete(probe) => Some(probe.sequence),
+            ProbeStatus::Failed(probe) => Some(probe.sequence),
+            ProbeStatus::NotSent | ProbeStatus::Skipped => None,
+        })
+        .map(|sequence| sequence.0)
+}
+
+/// A trace identifier for the `index`th traced address, distinct from those of the other
+/// addresses and of other processes.
+///
+/// trippy takes replies carrying its default identifier 0 as its own, whatever tracer sent them.
+fn trace_identifier(index: usize) -> u16 {
+    let id = (process::id() as usize + index) % usize::from(u16::MAX);
+    id as u16 + 1
+}
+
+fn build_tracer(
+    hostname: &Hostname,
+    addr: IpAddr,
+    options: &TraceOptions,
+    max_rounds: Option<usize>,
+    index: usize,
+    initial_sequence: u16,
+) -> color_eyre::Result<(Tracer, String)> {
+    let protocol = options.protocol;
+    // The port of a pasted URL is that of a TCP service, UDP probes to it are rarely answered.
+    let port = match protocol {
+        TraceProtocol::Tcp => options.port.or(hostname.port()),
+        TraceProtocol::Icmp | TraceProtocol::Udp => options.port,
     };
+    let port_direction = protocol.port_direction(port);
 
     let tracer = Builder::new(addr)
-        .interface(interface)
-        .source_addr(src_addr)
-        .protocol(Protocol::Udp)
+        .interface(options.interface.clone())
+        .source_addr(options.source)
+        .protocol(protocol.into())
         .port_direction(port_direction)
-        .packet_size(52)
-        .first_ttl(first_ttl)
-        .max_ttl(max_ttl)
-        .tos(tos)
+        .packet_size(options.packet_size)
+        .first_ttl(options.first_ttl)
+        .max_ttl(options.max_ttl)
+        .tos(options.tos)
         .max_flows(1)
-        .max_rounds(Some(nqueries))
-        .min_round_duration(Duration::from_millis(pausemecs))
-        .max_round_duration(Duration::from_millis(pausemecs))
+        .max_rounds(max_rounds)
+        .trace_identifier(trace_identifier(index))
+        .initial_sequence(initial_sequence)
+        .min_round_duration(Duration::from_millis(options.interval))
+        .max_round_duration(Duration::from_millis(options.interval))
         .build()?;
-    tracer.run()?;
 
+    let protocol = match protocol.dest_port(port) {
+        Some(port) => format!("{protocol} port {port}"),
+        None => protocol.to_string(),
+    };
+    let summary = format!(
+        "Traceroute to {} ({}) over {protocol}, {} hops max, {} byte packets",
+        &hostname,
+        tracer.target_addr(),
+        tracer.max_ttl().0,
+        tracer.packet_size().0
+    );
+    Ok((tracer, summary))
+}
+
+/// Converts the current state of `tracer` into [`TraceData`].
+///
+/// With `lazy` set, hostnames that are not resolved yet are looked up in the background.
+fn trace_data(
+    tracer: &Tracer,
+    resolver: &DnsResolver,
+    summary: String,
+    lazy: bool,
+    with_as_info: bool,
+) -> color_eyre::Result<TraceData> {
     let snapshot = &tracer.snapshot();
     if let Some(err) = snapshot.error() {
         return Err(color_eyre::eyre::eyre!("error: {err}"));
@@ -103,47 +910,230 @@ pub fn traceroute(hostname: &str) -> color_eyre::Result<TraceData> {
     let mut hops = Vec::new();
     for hop in snapshot.hops() {
         let ttl = hop.ttl();
-        let samples: String = hop
+        // trippy keeps the newest sample first and records lost probes as zero.
+        let samples: Vec<Option<Duration>> = hop
             .samples()
             .iter()
-            .map(|s| format!("{:.3} ms", s.as_secs_f64() * 1000_f64))
-            .join("  ");
+            .rev()
+            .map(|s| (!s.is_zero()).then_some(*s))
+            .collect();
+        let stats = HopStats::from(hop);
         if hop.addr_count() > 0 {
             for (i, addr) in hop.addrs().enumerate() {
-                let host = resolver.reverse_lookup(*addr).to_string();
+                let entry = match (lazy, with_as_info) {
+                    (true, true) => resolver.lazy_reverse_lookup_with_asinfo(*addr),
+                    (true, false) => resolver.lazy_reverse_lookup(*addr),
+                    (false, true) => resolver.reverse_lookup_with_asinfo(*addr),
+                    (false, false) => resolver.reverse_lookup(*addr),
+                };
+                let (host, as_info) = reverse_dns(entry);
                 if i != 0 {
                     hops.push(Hop {
                         ttl: None,
                         host: Some(host),
                         address: Some(addr.to_string()),
+                        as_info,
+                        geo: None,
                         samples: samples.clone(),
+                        stats,
                     });
                 } else {
                     hops.push(Hop {
-                        ttl: Some(ttl.to_string()),
+                        ttl: Some(ttl),
                         host: Some(host),
                         address: Some(addr.to_string()),
+                        as_info,
+                        geo: None,
                         samples: samples.clone(),
+                        stats,
                     });
                 }
             }
         } else {
             hops.push(Hop {
-                ttl: Some(ttl.to_string()),
+                ttl: Some(ttl),
                 host: None,
                 address: None,
+                as_info: None,
+                geo: None,
                 samples: samples.clone(),
+                stats,
             });
         }
     }
     Ok(TraceData {
-        summary: format!(
-            "Traceroute to {} ({}), {} hops max, {} byte packets",
-            &hostname,
-            tracer.target_addr(),
-            tracer.max_ttl().0,
-            tracer.packet_size().0
-        ),
+        summary,
         hops,
+        with_as_info,
+        with_geo: false,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The statistics trippy keeps for one batch of round-trip times, `None` for a lost probe.
+    fn batch_stats(rtts: &[Option<f64>]) -> HopStats {
+        let received: Vec<f64> = rtts.iter().flatten().copied().collect();
+        let n = received.len() as f64;
+        let avg = received.iter().sum::<f64>() / n;
+        let m2: f64 = received.iter().map(|rtt| (rtt - avg) * (rtt - avg)).sum();
+        HopStats {
+            sent: rtts.len(),
+            received: received.len(),
+            loss_pct: (rtts.len() - received.len()) as f64 / rtts.len() as f64 * 100_f64,
+            best_ms: received.iter().copied().reduce(f64::min),
+            avg_ms: Some(avg),
+            worst_ms: received.iter().copied().reduce(f64::max),
+            stddev_ms: Some((m2 / (n - 1_f64)).sqrt()),
+            jitter_ms: Some(0_f64),
+        }
+    }
+
+    fn hop(ttl: Option<u8>, address: &str, rtts: &[Option<f64>]) -> Hop {
+        Hop {
+            ttl,
+            host: Some(address.to_string()),
+            address: Some(address.to_string()),
+            as_info: None,
+            geo: None,
+            samples: rtts
+                .iter()
+                .map(|rtt| rtt.map(|ms| Duration::from_secs_f64(ms / 1000_f64)))
+                .collect(),
+            stats: batch_stats(rtts),
+        }
+    }
+
+    fn trace(hops: Vec<Hop>) -> TraceData {
+        TraceData {
+            summary: String::from("Traceroute"),
+            hops,
+            with_as_info: false,
+            with_geo: false,
+        }
+    }
+
+    fn addresses(trace_data: &TraceData) -> Vec<(Option<u8>, String)> {
+        let mut rows = Vec::new();
+        trace_data.hops(|hop| rows.push((hop.ttl(), hop.address())));
+        rows
+    }
+
+    #[test]
+    fn rtts_round_trip_with_lost_probes() {
+        let hop = hop(Some(1), "192.0.2.1", &[Some(1.5), None, Some(2.0)]);
+        let json = serde_json::to_value(&hop).unwrap();
+        assert_eq!(json["rtts_ms"], serde_json::json!([1.5, null, 2.0]));
+
+        let hop: Hop = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            hop.samples(),
+            [
+                Some(Duration::from_micros(1500)),
+                None,
+                Some(Duration::from_millis(2))
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_invalid_rtts() {
+        let json = r#"{"ttl": 1, "host": null, "address": null, "rtts_ms": [1.0, -2.0],
+            "stats": {"sent": 2, "received": 2, "loss_pct": 0.0}}"#;
+        assert!(serde_json::from_str::<Hop>(json).is_err());
+    }
+
+    #[test]
+    fn merged_stats_match_a_single_batch() {
+        let earlier = [Some(1.0), Some(4.0), None, Some(2.5)];
+        let later = [Some(3.0), None, None, Some(7.5), Some(2.0)];
+        let whole: Vec<_> = earlier.iter().chain(&later).copied().collect();
+
+        let merged = batch_stats(&earlier).merge(&batch_stats(&later));
+        let expected = batch_stats(&whole);
+        assert_eq!(merged.sent, 9);
+        assert_eq!(merged.received, 6);
+        assert!((merged.loss_pct - expected.loss_pct).abs() < 1e-9);
+        assert_eq!(merged.best_ms, Some(1.0));
+        assert_eq!(merged.worst_ms, Some(7.5));
+        assert!((merged.avg_ms.unwrap() - expected.avg_ms.unwrap()).abs() < 1e-9);
+        assert!((merged.stddev_ms.unwrap() - expected.stddev_ms.unwrap()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn merged_stats_of_a_lost_batch() {
+        let received = batch_stats(&[Some(2.0), Some(4.0)]);
+        let lost = HopStats {
+            sent: 2,
+            ..HopStats::default()
+        };
+
+        let merged = received.merge(&lost);
+        assert_eq!((merged.sent, merged.received), (4, 2));
+        assert_eq!(merged.loss_pct, 50.0);
+        assert_eq!(merged.avg_ms, Some(3.0));
+        assert_eq!(merged.stddev_ms, received.stddev_ms);
+
+        let merged = lost.merge(&lost);
+        assert_eq!(merged.loss_pct, 100.0);
+        assert_eq!(merged.avg_ms, None);
+        assert_eq!(merged.stddev_ms, None);
+    }
+
+    #[test]
+    fn merge_keeps_the_addresses_of_each_ttl_in_order() {
+        let earlier = trace(vec![
+            hop(Some(1), "192.0.2.1", &[Some(1.0)]),
+            hop(None, "192.0.2.2", &[Some(1.0)]),
+            hop(Some(2), "198.51.100.1", &[Some(2.0)]),
+        ]);
+        let later = trace(vec![
+            hop(Some(1), "192.0.2.2", &[None]),
+            hop(None, "192.0.2.3", &[None]),
+            hop(Some(2), "198.51.100.1", &[Some(3.0)]),
+            hop(Some(3), "203.0.113.1", &[Some(4.0)]),
+        ]);
+
+        let merged = earlier.merge(later);
+        assert_eq!(
+            addresses(&merged),
+            [
+                (Some(1), String::from("192.0.2.1")),
+                (None, String::from("192.0.2.2")),
+                (None, String::from("192.0.2.3")),
+                (Some(2), String::from("198.51.100.1")),
+                (Some(3), String::from("203.0.113.1")),
+            ]
+        );
+        for hop in merged.ttl_hops(0) {
+            assert_eq!(hop.samples().len(), 2);
+            assert_eq!((hop.stats().sent, hop.stats().received), (2, 1));
+        }
+        assert_eq!(merged.ttl_hops(2).len(), 3);
+        assert_eq!(merged.ttl_hops(3)[0].stats().avg_ms, Some(2.5));
+        assert_eq!(merged.ttl_hops(4).len(), 1);
+        assert!(merged.ttl_hops(5).is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_a_ttl_without_replies() {
+        let earlier = trace(vec![hop(Some(1), "192.0.2.1", &[Some(1.0)])]);
+        let mut lost = hop(Some(1), "*", &[None]);
+        lost.address = None;
+        lost.stats = HopStats {
+            sent: 1,
+            loss_pct: 100.0,
+            ..HopStats::default()
+        };
+
+        let merged = earlier.merge(trace(vec![lost.clone()]));
+        assert_eq!(addresses(&merged), [(Some(1), String::from("192.0.2.1"))]);
+        assert_eq!(merged.ttl_hops(0)[0].stats().loss_pct, 50.0);
+
+        let merged = trace(vec![lost.clone()]).merge(trace(vec![lost]));
+        assert_eq!(addresses(&merged), [(Some(1), String::from("*"))]);
+        assert_eq!(merged.ttl_hops(0)[0].stats().sent, 2);
+    }
+}
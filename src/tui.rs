@@ -1,156 +1,1157 @@
-use crate::{dns_leak::DnsData, trace::TraceData};
+use crate::{
+    check::Verdict,
+    dns_leak::{self, Asn, LeakReport, LeakResult, Provider},
+    geoip::GeoIp,
+    history::{self, Run},
+    policy::Policy,
+    table,
+    trace::{self, Hop, TraceControl, TraceData, TraceOptions, TraceUpdate},
+    validation::Hostname,
+};
+use chrono::{DateTime, Local};
 use ratatui::{
     crossterm::{
         self,
         event::{Event, KeyCode},
     },
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Flex, Layout, Rect},
     style::{Color, Style, Stylize},
-    text::Line,
-    widgets::*,
+    symbols::Marker,
+    text::{Line, Span},
+    widgets::{
+        canvas::{Canvas, Line as CanvasLine, Map, MapResolution},
+        *,
+    },
+    Frame,
 };
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+const MAX_HISTORY: usize = 10;
+
+const SPARKLINE_WIDTH: usize = 12;
+
+/// The settings used to (re-)run the leak test and the traceroute from the TUI.
+pub struct Runner {
+    pub provider: Provider,
+    pub api_url: Option<Url>,
+    pub ipv6_leak_test: bool,
+    pub hostname: Hostname,
+    pub trace_options: TraceOptions,
+    pub geoip: GeoIp,
+    pub save_history: bool,
+}
+
+impl Runner {
+    fn leak_test(&self) -> Receiver<Vec<LeakResult>> {
+        let (tx, rx) = mpsc::channel();
+        dns_leak::spawn_leak_tests(self.provider, self.api_url.clone(), self.ipv6_leak_test, tx);
+        rx
+    }
+
+    fn traceroute(&self, previous: &TraceControl) -> (Receiver<TraceUpdate>, TraceControl) {
+        let (tx, rx) = mpsc::channel();
+        let control = trace::spawn_traceroute(
+            self.hostname.clone(),
+            self.trace_options.clone(),
+            tx,
+            previous,
+        );
+        (rx, control)
+    }
+}
 
 struct App {
     is_running: bool,
-    data: Vec<DnsData>,
-    state: ratatui::widgets::TableState,
+    tick: usize,
+    view: View,
+    runner: Runner,
+    /// One leak test per address family.
+    reports: Option<Vec<LeakResult>>,
+    reports_at: Option<DateTime<Local>>,
+    /// Set while a leak test is running.
+    leak_results: Option<Receiver<Vec<LeakResult>>>,
+    policy: Policy,
+    dns_states: BTreeMap<usize, TableState>,
+    focus: Focus,
+    traces: BTreeMap<usize, Result<TraceData, String>>,
+    trace_states: BTreeMap<usize, TableState>,
+    /// Set while the details of the selected hop are shown.
+    hop_popup: bool,
+    show_chart: bool,
+    traces_at: Option<DateTime<Local>>,
+    trace_updates: Receiver<TraceUpdate>,
+    trace_control: TraceControl,
+    /// The updates of a re-run, only shown once it completes so the previous traces stay
+    /// visible until then.
+    rerun_traces: Option<BTreeMap<usize, TraceUpdate>>,
+    /// Set when only the first of the hostname's addresses is traced.
+    trace_truncated: bool,
+    /// Recently traced hostnames, most recent first.
+    history: Vec<String>,
+    prompt: Option<Prompt>,
+    /// Set when the shown results have not been stored in the history yet.
+    unsaved: bool,
+    /// Stored runs, oldest first.
+    runs: Vec<Run>,
+    runs_state: TableState,
+    runs_error: Option<String>,
+}
+
+/// What is shown below the header.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum View {
+    Results,
+    Map,
+    History,
+}
+
+impl View {
+    const ALL: [Self; 3] = [Self::Results, Self::Map, Self::History];
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Results => "Results",
+            Self::Map => "Map",
+            Self::History => "History",
+        }
+    }
+}
+
+/// The panel that receives the navigation keys.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    /// The leak test of the address family with this index.
+    Dns(usize),
+    /// The traceroute of the address with this index.
+    Trace(usize),
+}
+
+/// The input for a new traceroute target.
+#[derive(Default)]
+struct Prompt {
+    input: String,
+    error: Option<String>,
+    history: ListState,
+}
+
+impl App {
+    /// Stores the shown results in the history before they are replaced.
+    fn save_run(&mut self) {
+        if !self.unsaved || !self.runner.save_history {
+            return;
+        }
+        let Some(reports) = self
+            .reports
+            .as_ref()
+            .filter(|r| r.iter().any(Result::is_ok))
+        else {
+            return;
+        };
+        let traces = self
+            .traces
+            .values()
+            .filter_map(|trace| trace.as_ref().ok())
+            .cloned()
+            .collect();
+        let run = Run::new(&self.runner.hostname, reports.clone(), traces, &self.policy);
+        match history::append(&run) {
+            Ok(()) => self.runs.push(run),
+            Err(e) => self.runs_error = Some(e.to_string()),
+        }
+        self.unsaved = false;
+    }
+
+    /// The reports of the families whose leak test succeeded.
+    fn leak_reports(&self) -> impl Iterator<Item = &LeakReport> {
+        self.reports.iter().flatten().flatten()
+    }
+
+    fn rerun_leak_test(&mut self) {
+        self.save_run();
+        self.leak_results = Some(self.runner.leak_test());
+    }
+
+    fn rerun_traceroute(&mut self) {
+        self.save_run();
+        self.trace_control.stop();
+        (self.trace_updates, self.trace_control) = self.runner.traceroute(&self.trace_control);
+        self.rerun_traces = Some(BTreeMap::new());
+    }
+
+    /// Cycles through the DNS tables that have results and then the traces.
+    fn focus_next(&mut self) {
+        let dns = self
+            .reports
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(index, _)| Focus::Dns(index));
+        let panels: Vec<Focus> = dns
+            .chain(self.traces.keys().copied().map(Focus::Trace))
+            .collect();
+        let next = panels
+            .iter()
+            .position(|focus| *focus == self.focus)
+            .map_or(0, |i| i + 1);
+        self.focus = panels
+            .get(next)
+            .or(panels.first())
+            .copied()
+            .unwrap_or(Focus::Dns(0));
+    }
+
+    fn focused_state(&mut self) -> &mut TableState {
+        if self.view == View::History {
+            return &mut self.runs_state;
+        }
+        match self.focus {
+            Focus::Dns(index) => self
+                .dns_states
+                .entry(index)
+                .or_insert_with(|| TableState::default().with_selected(0)),
+            Focus::Trace(index) => self
+                .trace_states
+                .entry(index)
+                .or_insert_with(|| TableState::default().with_selected(0)),
+        }
+    }
+
+    fn change_hostname(&mut self, hostname: Hostname) {
+        self.save_run();
+        let target = hostname.to_string();
+        self.history.retain(|h| h != &target);
+        self.history.insert(0, target);
+        self.history.truncate(MAX_HISTORY);
+        self.runner.hostname = hostname;
+        self.rerun_traceroute();
+    }
+
+    fn handle_prompt_key(&mut self, code: KeyCode) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        match code {
+            KeyCode::Esc => {
+                self.prompt = None;
+            }
+            KeyCode::Enter => match Hostname::new(prompt.input.clone()) {
+                Ok(hostname) => {
+                    self.prompt = None;
+                    self.change_hostname(hostname);
+                }
+                Err(e) => prompt.error = Some(e.to_string()),
+            },
+            KeyCode::Backspace => {
+                prompt.input.pop();
+                prompt.error = None;
+            }
+            KeyCode::Char(c) => {
+                prompt.input.push(c);
+                prompt.error = None;
+            }
+            KeyCode::Down | KeyCode::Up => {
+                let last = self.history.len().saturating_sub(1);
+                let selected = match (code, prompt.history.selected()) {
+                    (KeyCode::Down, Some(i)) => (i + 1).min(last),
+                    (KeyCode::Up, Some(i)) => i.saturating_sub(1),
+                    _ => 0,
+                };
+                if let Some(target) = self.history.get(selected) {
+                    prompt.history.select(Some(selected));
+                    prompt.input = target.clone();
+                    prompt.error = None;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn receive(&mut self) {
+        if let Some(mut results) = self.leak_results.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.runner
+                .geoip
+                .enrich_reports(results.iter_mut().flatten());
+            self.unsaved |= results.iter().any(Result::is_ok);
+            self.reports = Some(results);
+            self.reports_at = Some(Local::now());
+            self.leak_results = None;
+        }
+        while let Ok(mut update) = self.trace_updates.try_recv() {
+            if let Ok(trace_data) = &mut update.result {
+                trace_data.enrich(&self.runner.geoip);
+            }
+            match &mut self.rerun_traces {
+                Some(updates) => {
+                    updates.insert(update.index, update);
+                }
+                None => self.show_trace(update),
+            }
+        }
+        let rerun_complete = self.rerun_traces.as_ref().is_some_and(|updates| {
+            self.trace_control.is_finished()
+                || (!updates.is_empty() && updates.values().all(|update| update.complete))
+        });
+        if rerun_complete {
+            self.traces.clear();
+            self.trace_states.clear();
+            for update in self
+                .rerun_traces
+                .take()
+                .into_iter()
+                .flat_map(BTreeMap::into_values)
+            {
+                self.show_trace(update);
+            }
+        }
+    }
+
+    fn show_trace(&mut self, update: TraceUpdate) {
+        self.unsaved |= update.result.is_ok();
+        self.trace_truncated = update.truncated;
+        self.traces.insert(update.index, update.result);
+        self.traces_at = Some(Local::now());
+    }
 }
 
-pub fn run_tui(dns_data: Vec<DnsData>, trace_data: TraceData) -> color_eyre::Result<()> {
+pub fn run_tui(runner: Runner, policy: Policy) -> color_eyre::Result<()> {
+    let history = vec![runner.hostname.to_string()];
+    let (runs, runs_error) = match history::load() {
+        Ok((runs, warnings)) if warnings.is_empty() => (runs, None),
+        Ok((runs, warnings)) => (runs, Some(warnings.join("; "))),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    let leak_results = runner.leak_test();
+    let (trace_updates, trace_control) = runner.traceroute(&TraceControl::default());
     let mut app = App {
         is_running: true,
-        data: dns_data,
-        state: TableState::default(),
+        tick: 0,
+        view: View::Results,
+        runner,
+        reports: None,
+        reports_at: None,
+        leak_results: Some(leak_results),
+        policy,
+        dns_states: BTreeMap::new(),
+        focus: Focus::Dns(0),
+        traces: BTreeMap::new(),
+        trace_states: BTreeMap::new(),
+        hop_popup: false,
+        show_chart: false,
+        traces_at: None,
+        trace_updates,
+        trace_control,
+        rerun_traces: None,
+        trace_truncated: false,
+        history,
+        prompt: None,
+        unsaved: false,
+        runs,
+        runs_state: TableState::default().with_selected(0),
+        runs_error,
     };
-    app.state.select(Some(0));
     let mut terminal = ratatui::init();
     while app.is_running {
+        app.tick = app.tick.wrapping_add(1);
+        app.receive();
         terminal.draw(|f| {
-            let chunks = Layout::new(
-                Direction::Vertical,
-                [
-                    Constraint::Min(3),
-                    Constraint::Percentage(50),
-                    Constraint::Percentage(50),
-                ]
-                .as_ref(),
-            )
-            .split(f.area());
+            let chunks =
+                Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).split(f.area());
 
-            if let Some(ip) = app.data.iter().find(|v| v.type_field == "ip") {
-                let ip = ip.clone();
-                f.render_widget(
-                    Paragraph::new(Line::from(vec![
-                        ip.ip.italic(),
+            let ips = app
+                .leak_reports()
+                .filter_map(|report| Some((report.family, report.ip.as_ref()?)))
+                .enumerate()
+                .flat_map(|(i, (family, ip))| {
+                    let mut spans = Vec::new();
+                    if i != 0 {
+                        spans.push(" | ".into());
+                    }
+                    if let Some(family) = family {
+                        spans.push(format!("{family}: ").into());
+                    }
+                    spans.extend([
+                        ip.ip.to_string().italic(),
                         " [".into(),
-                        ip.country_name.yellow(),
+                        format!("{} {}", ip.country_name, ip.flag()).yellow(),
                         ", ".into(),
-                        ip.asn.green(),
+                        ip.asn_label().green(),
                         "]".into(),
-                    ]))
-                    .block(
-                        Block::bordered().title("| Your IP |").title_top(
-                            ratatui::text::Span::from("dnsleaktest-tui")
+                    ]);
+                    spans
+                })
+                .collect::<Vec<_>>();
+            let ips = if app.reports.is_none() {
+                loading("Looking up your IP...", app.tick)
+            } else {
+                Line::from(ips)
+            };
+            f.render_widget(
+                Paragraph::new(ips).block(
+                    Block::bordered()
+                        .title("| Your IP |")
+                        .title_top(
+                            Span::from("dnsleaktest-tui")
                                 .yellow()
                                 .bold()
                                 .into_right_aligned_line(),
-                        ),
-                    ),
-                    chunks[0],
-                );
-            }
-            let headers = Row::new(vec!["IP".cyan(), "Country".cyan(), "ASN".cyan()]);
-            let rows = app
-                .data
-                .iter()
-                .filter(|result| result.type_field == "dns")
-                .map(|result| {
-                    Row::new(vec![
-                        Cell::from(result.ip.clone()),
-                        Cell::from(result.country_name.clone()),
-                        Cell::from(result.asn.clone()),
-                    ])
-                })
-                .collect::<Vec<Row>>();
-            let table = Table::new(
-                rows,
-                [
-                    Constraint::Min(20),
-                    Constraint::Min(20),
-                    Constraint::Fill(3),
-                ]
-                .as_ref(),
-            )
-            .header(headers)
-            .highlight_style(Style::default().bg(Color::White).fg(Color::Black))
-            .highlight_symbol("> ")
-            .block(
-                Block::bordered().title("| DNS Leak Test |").title_bottom(
-                    app.data
-                        .iter()
-                        .find(|v| v.type_field == "conclusion")
-                        .map(|v| v.ip.clone().italic())
-                        .unwrap_or_default()
-                        .into_right_aligned_line(),
+                        )
+                        .title_bottom(view_tabs(app.view).right_aligned()),
                 ),
+                chunks[0],
             );
-            f.render_stateful_widget(table, chunks[1], &mut app.state);
-
-            let headers = Row::new(vec![
-                "TTL".cyan(),
-                "Host".cyan(),
-                "Address".cyan(),
-                "Samples".cyan(),
-            ]);
 
-            let mut rows = Vec::new();
-            trace_data.hops(|hop| {
-                let ttl = hop.ttl().unwrap_or_default();
-                let host = hop.host();
-                let address = hop.address();
-                let samples = hop.samples();
-                rows.push(Row::new(vec![
-                    Cell::from(ttl),
-                    Cell::from(host),
-                    Cell::from(address),
-                    Cell::from(samples),
-                ]));
-            });
-
-            let table = Table::new(
-                rows,
-                [
-                    Constraint::Max(5),
-                    Constraint::Max(20),
-                    Constraint::Max(15),
-                    Constraint::Fill(1),
-                ]
-                .as_ref(),
-            )
-            .header(headers)
-            .highlight_style(Style::default().bg(Color::White).fg(Color::Black))
-            .highlight_symbol("> ")
-            .block(Block::bordered().title(format!("| {} |", trace_data.summary().italic())));
-
-            f.render_widget(table, chunks[2]);
+            match app.view {
+                View::Results => render_results(f, &mut app, chunks[1]),
+                View::Map => render_map(f, &app, chunks[1]),
+                View::History => render_history(f, &mut app, chunks[1]),
+            }
+            if let Some(prompt) = app.prompt.as_mut() {
+                render_prompt(f, prompt, &app.history);
+            }
         })?;
 
+        if !crossterm::event::poll(Duration::from_millis(100))? {
+            continue;
+        }
         let event = crossterm::event::read()?;
         if let Event::Key(key) = event {
+            if app.prompt.is_some() {
+                app.handle_prompt_key(key.code);
+                continue;
+            }
+            if app.hop_popup {
+                if matches!(key.code, KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q')) {
+                    app.hop_popup = false;
+                }
+                continue;
+            }
             match key.code {
                 KeyCode::Char('q') => {
                     app.is_running = false;
                 }
                 KeyCode::Down => {
-                    app.state.select_next();
+                    app.focused_state().select_next();
                 }
                 KeyCode::Up => {
-                    app.state.select_previous();
+                    app.focused_state().select_previous();
+                }
+                KeyCode::PageDown => {
+                    app.focused_state().scroll_down_by(10);
+                }
+                KeyCode::PageUp => {
+                    app.focused_state().scroll_up_by(10);
+                }
+                KeyCode::Home => {
+                    app.focused_state().select_first();
+                }
+                KeyCode::End => {
+                    app.focused_state().select_last();
+                }
+                KeyCode::Tab => {
+                    app.focus_next();
+                }
+                KeyCode::Enter => {
+                    app.hop_popup =
+                        app.view == View::Results && matches!(app.focus, Focus::Trace(_));
+                }
+                KeyCode::Char(c @ '1'..='9') => {
+                    let index = c as usize - '1' as usize;
+                    if let Some(view) = View::ALL.get(index) {
+                        app.view = *view;
+                    }
+                }
+                KeyCode::Char('c') => {
+                    app.show_chart = !app.show_chart;
+                }
+                KeyCode::Char('p') => {
+                    app.trace_control.toggle_pause();
+                }
+                KeyCode::Char('r') => {
+                    app.rerun_leak_test();
+                }
+                KeyCode::Char('t') => {
+                    app.rerun_traceroute();
+                }
+                KeyCode::Char('R') => {
+                    app.rerun_leak_test();
+                    app.rerun_traceroute();
+                }
+                KeyCode::Char('/') | KeyCode::Char('h') => {
+                    app.prompt = Some(Prompt::default());
                 }
                 _ => {}
             }
         }
     }
+    app.save_run();
     ratatui::restore();
     Ok(())
 }
+
+fn render_results(f: &mut Frame, app: &mut App, area: Rect) {
+    let [dns_area, trace_area] =
+        Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(area);
+    let leak_status = status(app.reports_at, app.leak_results.is_some(), app.tick);
+    match &app.reports {
+        None => f.render_widget(
+            Paragraph::new(loading("Running DNS leak test...", app.tick))
+                .block(Block::bordered().title("| DNS Leak Test |")),
+            dns_area,
+        ),
+        Some(results) => {
+            let dns_chunks = Layout::horizontal(
+                results
+                    .iter()
+                    .map(|_| Constraint::Ratio(1, results.len() as u32)),
+            )
+            .split(dns_area);
+            for ((index, result), area) in results.iter().enumerate().zip(dns_chunks.iter()) {
+                match result {
+                    Ok(report) => {
+                        let table = dns_table(
+                            report,
+                            &app.policy,
+                            leak_status.clone(),
+                            app.focus == Focus::Dns(index),
+                        );
+                        let state = app
+                            .dns_states
+                            .entry(index)
+                            .or_insert_with(|| TableState::default().with_selected(0));
+                        f.render_stateful_widget(table, *area, state);
+                    }
+                    Err(e) => f.render_widget(
+                        Paragraph::new(e.to_string().red())
+                            .wrap(Wrap { trim: true })
+                            .block(
+                                Block::bordered()
+                                    .title("| DNS Leak Test |")
+                                    .title_bottom(leak_status.clone()),
+                            ),
+                        *area,
+                    ),
+                }
+            }
+        }
+    }
+
+    if app.traces.is_empty() {
+        f.render_widget(
+            Paragraph::new(loading("Tracing...", app.tick))
+                .block(Block::bordered().title("| Traceroute |")),
+            trace_area,
+        );
+        return;
+    }
+    let trace_area = if app.show_chart {
+        let [trace_area, chart_area] =
+            Layout::horizontal([Constraint::Percentage(75), Constraint::Percentage(25)])
+                .areas(trace_area);
+        render_selected_latency(f, app, chart_area);
+        trace_area
+    } else {
+        trace_area
+    };
+    let trace_chunks = Layout::vertical(
+        app.traces
+            .values()
+            .map(|_| Constraint::Ratio(1, app.traces.len() as u32)),
+    )
+    .split(trace_area);
+    let mut trace_status = status(app.traces_at, app.rerun_traces.is_some(), app.tick);
+    if app.trace_truncated {
+        trace_status.push_span(
+            format!(
+                " {} has more addresses, pass --all-addresses to trace them all ",
+                app.runner.hostname
+            )
+            .yellow(),
+        );
+    }
+    for ((index, trace), area) in app.traces.iter().zip(trace_chunks.iter()) {
+        match trace {
+            Ok(trace_data) => {
+                let table = trace_table(
+                    trace_data,
+                    app.trace_control.is_paused(),
+                    trace_status.clone(),
+                    app.focus == Focus::Trace(*index),
+                );
+                let state = app
+                    .trace_states
+                    .entry(*index)
+                    .or_insert_with(|| TableState::default().with_selected(0));
+                f.render_stateful_widget(table, *area, state);
+            }
+            Err(e) => f.render_widget(
+                Paragraph::new(e.as_str().red()).block(
+                    Block::bordered()
+                        .title("| Traceroute |")
+                        .title_bottom(trace_status.clone()),
+                ),
+                *area,
+            ),
+        }
+    }
+
+    if let (true, Focus::Trace(index)) = (app.hop_popup, app.focus) {
+        let selected = app.trace_states.get(&index).and_then(TableState::selected);
+        if let (Some(Ok(trace_data)), Some(selected)) = (app.traces.get(&index), selected) {
+            render_hop(f, trace_data.ttl_hops(selected));
+        }
+    }
+}
+
+fn render_map(f: &mut Frame, app: &App, area: Rect) {
+    let geoip = &app.runner.geoip;
+    let locate = |ip: IpAddr| geoip.lookup(ip).and_then(|geo| geo.coordinates());
+    let ours: Vec<(f64, f64)> = app
+        .leak_reports()
+        .filter_map(|report| locate(report.ip.as_ref()?.ip))
+        .collect();
+    let resolvers: Vec<((f64, f64), Color)> = app
+        .leak_reports()
+        .flat_map(|report| &report.resolvers)
+        .filter_map(|resolver| {
+            let color = if app.policy.is_empty() {
+                Color::Magenta
+            } else if app.policy.evaluate(resolver).is_allowed() {
+                Color::Green
+            } else {
+                Color::Red
+            };
+            Some((locate(resolver.ip)?, color))
+        })
+        .collect();
+    // Every path starts at our own address and follows the geolocated hops in TTL order.
+    let paths: Vec<Vec<(f64, f64)>> = app
+        .traces
+        .values()
+        .filter_map(|trace| trace.as_ref().ok())
+        .map(|trace_data| {
+            let mut path: Vec<_> = ours.first().copied().into_iter().collect();
+            trace_data.hops(|hop| {
+                if hop.ttl().is_some() {
+                    path.extend(hop.geo().and_then(|geo| geo.coordinates()));
+                }
+            });
+            path
+        })
+        .collect();
+
+    let mut block = Block::bordered()
+        .title("| Map |")
+        .title_bottom(Line::from(vec![
+            " ● ".yellow(),
+            "you ".into(),
+            " ◆ ".magenta(),
+            "resolver ".into(),
+            " • ".cyan(),
+            "hop ".into(),
+        ]));
+    if geoip.is_empty() {
+        block = block.title_bottom(
+            " Pass --geoip-db with a city database to plot addresses "
+                .italic()
+                .into_right_aligned_line(),
+        );
+    }
+    let canvas = Canvas::default()
+        .block(block)
+        .marker(Marker::Braille)
+        .x_bounds([-180.0, 180.0])
+        .y_bounds([-90.0, 90.0])
+        .paint(|ctx| {
+            ctx.draw(&Map {
+                color: Color::DarkGray,
+                resolution: MapResolution::High,
+            });
+            ctx.layer();
+            for path in &paths {
+                for segment in path.windows(2) {
+                    ctx.draw(&CanvasLine {
+                        x1: segment[0].0,
+                        y1: segment[0].1,
+                        x2: segment[1].0,
+                        y2: segment[1].1,
+                        color: Color::Cyan,
+                    });
+                }
+                for (x, y) in path.iter().skip(usize::from(!ours.is_empty())) {
+                    ctx.print(*x, *y, "•".cyan());
+                }
+            }
+            for ((x, y), color) in &resolvers {
+                ctx.print(*x, *y, "◆".fg(*color));
+            }
+            for (x, y) in &ours {
+                ctx.print(*x, *y, "●".yellow());
+            }
+        });
+    f.render_widget(canvas, area);
+}
+
+fn render_history(f: &mut Frame, app: &mut App, area: Rect) {
+    let [runs_area, details_area] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(area);
+    let rows = app.runs.iter().rev().map(|run| {
+        let resolvers: usize = run.dns_leak.iter().map(|r| r.resolvers.len()).sum();
+        let hops: usize = run.traceroutes.iter().map(TraceData::hop_count).sum();
+        let color = match run.verdict {
+            Verdict::NoLeak => Color::Green,
+            Verdict::Leak => Color::Red,
+            Verdict::Inconclusive | Verdict::NetworkError | Verdict::InvalidResponse => {
+                Color::Yellow
+            }
+        };
+        Row::new(vec![
+            Cell::from(run.timestamp.format("%Y-%m-%d %H:%M:%S").to_string()),
+            Cell::from(run.target.clone()),
+            Cell::from(run.verdict.to_string().fg(color)),
+            Cell::from(resolvers.to_string()),
+            Cell::from(hops.to_string()),
+        ])
+    });
+    let mut block = Block::bordered().title("| History |");
+    if let Some(error) = &app.runs_error {
+        block = block.title_bottom(error.as_str().red());
+    } else if let Ok(path) = history::path() {
+        block = block.title_bottom(path.display().to_string().dark_gray());
+    }
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(19),
+            Constraint::Fill(1),
+            Constraint::Length(13),
+            Constraint::Length(4),
+            Constraint::Length(4),
+        ],
+    )
+    .header(Row::new(
+        ["Time", "Target", "Verdict", "DNS", "Hops"].map(|h| h.cyan()),
+    ))
+    .highlight_style(Style::default().bg(Color::White).fg(Color::Black))
+    .highlight_symbol("> ")
+    .block(block);
+    f.render_stateful_widget(table, runs_area, &mut app.runs_state);
+
+    let selected = app.runs_state.selected().unwrap_or_default();
+    let Some(run) = app.runs.iter().rev().nth(selected) else {
+        f.render_widget(
+            Paragraph::new("No runs stored yet.".italic()).block(Block::bordered()),
+            details_area,
+        );
+        return;
+    };
+    let [dns_area, trace_area] =
+        Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .areas(details_area);
+    let dns_chunks = Layout::horizontal(
+        run.dns_leak
+            .iter()
+            .map(|_| Constraint::Ratio(1, run.dns_leak.len() as u32)),
+    )
+    .split(dns_area);
+    for (report, area) in run.dns_leak.iter().zip(dns_chunks.iter()) {
+        let table = dns_table(report, &app.policy, Line::default(), false);
+        f.render_widget(table, *area);
+    }
+    let trace_chunks = Layout::vertical(
+        run.traceroutes
+            .iter()
+            .map(|_| Constraint::Ratio(1, run.traceroutes.len() as u32)),
+    )
+    .split(trace_area);
+    for (trace_data, area) in run.traceroutes.iter().zip(trace_chunks.iter()) {
+        let table = trace_table(trace_data, false, Line::default(), false);
+        f.render_widget(table, *area);
+    }
+}
+
+fn view_tabs(current: View) -> Line<'static> {
+    let mut spans = Vec::new();
+    for (i, view) in View::ALL.into_iter().enumerate() {
+        let tab = format!(" {} {} ", i + 1, view.title());
+        spans.push(if view == current {
+            tab.black().on_yellow()
+        } else {
+            tab.into()
+        });
+    }
+    Line::from(spans)
+}
+
+fn render_prompt(f: &mut Frame, prompt: &mut Prompt, history: &[String]) {
+    let area = popup_area(f.area(), 70, history.len() as u16 + 6);
+    let block = Block::bordered().title("| Traceroute to |").title_bottom(
+        " Enter: trace | Esc: cancel "
+            .italic()
+            .into_right_aligned_line(),
+    );
+    let inner = block.inner(area);
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(2),
+        Constraint::Fill(1),
+    ])
+    .split(inner);
+    f.render_widget(Paragraph::new(format!("> {}", prompt.input)), chunks[0]);
+    f.set_cursor_position((
+        chunks[0].x + 2 + prompt.input.chars().count() as u16,
+        chunks[0].y,
+    ));
+    if let Some(error) = &prompt.error {
+        f.render_widget(
+            Paragraph::new(error.as_str().red()).wrap(Wrap { trim: true }),
+            chunks[1],
+        );
+    }
+    let history = List::new(history.iter().map(String::as_str))
+        .block(Block::new().title("Recent".cyan()))
+        .highlight_style(Style::default().bg(Color::White).fg(Color::Black))
+        .highlight_symbol("> ");
+    f.render_stateful_widget(history, chunks[2], &mut prompt.history);
+}
+
+fn render_hop(f: &mut Frame, hops: &[Hop]) {
+    let Some(first) = hops.first() else {
+        return;
+    };
+    let stats = first.stats();
+    let samples = first
+        .samples()
+        .iter()
+        .map(|rtt| match rtt {
+            Some(rtt) => format!("{:.1}", rtt.as_secs_f64() * 1000_f64),
+            None => String::from("*"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut lines = vec![Line::from("Addresses".cyan())];
+    for hop in hops {
+        lines.push(Line::from(format!("  {} ({})", hop.address(), hop.host())));
+        if let Some(info) = hop.as_info() {
+            lines.push(Line::from(
+                format!("    {} {} {}", info.asn, info.prefix, info.name).dark_gray(),
+            ));
+        }
+        if let Some(geo) = hop.geo() {
+            let fields = [
+                geo.country_name.clone(),
+                geo.asn.as_ref().map(Asn::to_string),
+            ];
+            let text = fields.into_iter().flatten().collect::<Vec<_>>();
+            lines.push(Line::from(format!("    {}", text.join(", ")).dark_gray()));
+        }
+    }
+    lines.extend([
+        Line::default(),
+        Line::from("Statistics".cyan()),
+        Line::from(format!(
+            "  Sent {}, received {}, loss {:.1}%",
+            stats.sent, stats.received, stats.loss_pct
+        )),
+        Line::from(format!(
+            "  Best {} ms, avg {} ms, worst {} ms",
+            millis(stats.best_ms),
+            millis(stats.avg_ms),
+            millis(stats.worst_ms)
+        )),
+        Line::from(format!(
+            "  StDev {} ms, jitter {} ms",
+            millis(stats.stddev_ms),
+            millis(stats.jitter_ms)
+        )),
+        Line::default(),
+        Line::from("RTT samples (ms)".cyan()),
+        Line::from(samples.as_str()),
+    ]);
+
+    let title = match first.ttl() {
+        Some(ttl) => format!("| Hop {ttl} |"),
+        None => String::from("| Hop |"),
+    };
+    // The samples line wraps inside the 78 columns between the borders.
+    let height = lines.len() + samples.len() / 78 + 2;
+    let area = popup_area(f.area(), 80, height as u16);
+    f.render_widget(Clear, area);
+    f.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::bordered()
+                .title(title)
+                .title_bottom(" Esc: close ".italic().into_right_aligned_line()),
+        ),
+        area,
+    );
+}
+
+fn millis(value: Option<f64>) -> String {
+    value
+        .map(|ms| format!("{ms:.1}"))
+        .unwrap_or_else(|| String::from("-"))
+}
+
+/// A centered area of at most `width` by `height` cells.
+fn popup_area(area: Rect, width: u16, height: u16) -> Rect {
+    let [area] = Layout::horizontal([Constraint::Max(width)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::vertical([Constraint::Max(height)])
+        .flex(Flex::Center)
+        .areas(area);
+    area
+}
+
+fn loading(label: &str, tick: usize) -> Line<'static> {
+    Line::from(vec![
+        SPINNER[tick % SPINNER.len()].to_string().yellow(),
+        " ".into(),
+        label.to_string().italic(),
+    ])
+}
+
+/// When the shown results were collected, and whether newer ones are on the way.
+fn status(at: Option<DateTime<Local>>, running: bool, tick: usize) -> Line<'static> {
+    let mut spans = Vec::new();
+    if let Some(at) = at {
+        spans.push(format!(" {} ", at.format("%H:%M:%S")).dark_gray());
+        if running {
+            spans.push(Span::from(SPINNER[tick % SPINNER.len()].to_string()).yellow());
+            spans.push(" re-running ".italic());
+        }
+    }
+    Line::from(spans)
+}
+
+fn trace_table<'a>(
+    trace_data: &'a TraceData,
+    paused: bool,
+    status: Line<'a>,
+    focused: bool,
+) -> Table<'a> {
+    let trace_table = table::trace_table(trace_data);
+    let headers = Row::new(
+        trace_table
+            .headers
+            .iter()
+            .chain(&["Latency"])
+            .map(|header| header.cyan()),
+    );
+    let mut sparklines = Vec::new();
+    trace_data.hops(|hop| {
+        sparklines.push(match hop.ttl() {
+            Some(_) => sparkline(hop.samples()),
+            None => String::new(),
+        })
+    });
+    let rows = trace_table
+        .rows
+        .into_iter()
+        .zip(sparklines)
+        .map(|(row, sparkline)| {
+            let mut cells: Vec<Cell> = row.into_iter().map(Cell::from).collect();
+            cells.push(Cell::from(sparkline.green()));
+            Row::new(cells)
+        })
+        .collect::<Vec<Row>>();
+    let mut block = Block::bordered()
+        .title(format!("| {} |", trace_table.title.italic()))
+        .title_bottom(status);
+    if paused {
+        block = block.title_bottom("paused".yellow().into_right_aligned_line());
+    }
+    if focused {
+        block = block.border_style(Style::default().fg(Color::Yellow));
+    }
+
+    let mut widths = vec![Constraint::Max(5), Constraint::Fill(2), Constraint::Min(15)];
+    if trace_data.with_as_info() || trace_data.with_geo() {
+        widths.push(Constraint::Fill(2));
+    }
+    if trace_data.with_geo() {
+        widths.push(Constraint::Length(7));
+    }
+    widths.extend([
+        Constraint::Length(6),
+        Constraint::Length(4),
+        Constraint::Length(4),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(SPARKLINE_WIDTH as u16),
+    ]);
+
+    Table::new(rows, widths)
+        .header(headers)
+        .highlight_style(Style::default().bg(Color::White).fg(Color::Black))
+        .highlight_symbol("> ")
+        .block(block)
+}
+
+/// Draws the latest round-trip times with block characters, scaled between their extremes.
+///
+/// Lost probes are drawn as a dot.
+fn sparkline(samples: &[Option<Duration>]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let samples = &samples[samples.len().saturating_sub(SPARKLINE_WIDTH)..];
+    let min = samples.iter().flatten().min().copied().unwrap_or_default();
+    let max = samples.iter().flatten().max().copied().unwrap_or_default();
+    let range = (max - min).as_secs_f64();
+    samples
+        .iter()
+        .map(|sample| {
+            let Some(sample) = sample else {
+                return '·';
+            };
+            let level = if range > 0.0 {
+                (sample.saturating_sub(min).as_secs_f64() / range * 7.0).round() as usize
+            } else {
+                0
+            };
+            BARS[level.min(7)]
+        })
+        .collect()
+}
+
+/// Charts the selected hop of the focused trace, or of the first one.
+fn render_selected_latency(f: &mut Frame, app: &App, area: Rect) {
+    let index = match app.focus {
+        Focus::Trace(index) => index,
+        Focus::Dns(_) => app.traces.keys().next().copied().unwrap_or_default(),
+    };
+    let selected = app
+        .trace_states
+        .get(&index)
+        .and_then(TableState::selected)
+        .unwrap_or_default();
+    let hops = match app.traces.get(&index) {
+        Some(Ok(trace_data)) => trace_data.ttl_hops(selected),
+        _ => &[],
+    };
+    render_latency_chart(f, hops, area);
+}
+
+/// Plots every round-trip time of the hop in the order they were measured.
+///
+/// The line breaks where a probe was lost, which is marked on the x axis.
+fn render_latency_chart(f: &mut Frame, hops: &[Hop], area: Rect) {
+    let samples = hops.first().map(|hop| hop.samples()).unwrap_or_default();
+    let mut runs = Vec::new();
+    let mut run = Vec::new();
+    let mut lost = Vec::new();
+    for (i, rtt) in samples.iter().enumerate() {
+        let round = (i + 1) as f64;
+        match rtt {
+            Some(rtt) => run.push((round, rtt.as_secs_f64() * 1000_f64)),
+            None => {
+                lost.push((round, 0.0));
+                runs.push(std::mem::take(&mut run));
+            }
+        }
+    }
+    runs.push(run);
+    let count = samples.len().max(1) as f64;
+    let max = runs.iter().flatten().map(|(_, ms)| *ms).fold(0.0, f64::max);
+    let title = match hops.first().and_then(Hop::ttl) {
+        Some(ttl) => format!("| RTT of hop {ttl} |"),
+        None => String::from("| RTT |"),
+    };
+    let mut datasets: Vec<Dataset> = runs
+        .iter()
+        .map(|run| {
+            Dataset::default()
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(Color::Cyan))
+                .data(run)
+        })
+        .collect();
+    datasets.push(
+        Dataset::default()
+            .marker(Marker::Dot)
+            .graph_type(GraphType::Scatter)
+            .style(Style::default().fg(Color::Red))
+            .data(&lost),
+    );
+    let chart = Chart::new(datasets)
+        .block(Block::bordered().title(title))
+        .x_axis(
+            Axis::default()
+                .title("round".dark_gray())
+                .bounds([1.0, count])
+                .labels(["1".to_string(), format!("{count}")]),
+        )
+        .y_axis(
+            Axis::default()
+                .title("ms".dark_gray())
+                .bounds([0.0, max * 1.1])
+                .labels(["0".to_string(), format!("{max:.1}")]),
+        );
+    f.render_widget(chart, area);
+}
+
+fn dns_table<'a>(
+    report: &'a LeakReport,
+    policy: &Policy,
+    status: Line<'a>,
+    focused: bool,
+) -> Table<'a> {
+    let dns_table = table::dns_table(report, policy);
+    let headers = Row::new(dns_table.headers.iter().map(|header| header.cyan()));
+    let rows = dns_table
+        .rows
+        .into_iter()
+        .zip(&report.resolvers)
+        .map(|(row, resolver)| {
+            let mut cells: Vec<Cell> = row.into_iter().map(Cell::from).collect();
+            if let Some(status) = cells.get_mut(3) {
+                let color = if policy.evaluate(resolver).is_allowed() {
+                    Color::Green
+                } else {
+                    Color::Red
+                };
+                *status = status.clone().fg(color);
+            }
+            Row::new(cells)
+        })
+        .collect::<Vec<Row>>();
+    let mut widths = vec![
+        Constraint::Min(20),
+        Constraint::Min(20),
+        Constraint::Fill(3),
+    ];
+    if !policy.is_empty() {
+        widths.push(Constraint::Min(20));
+    }
+    let mut block = Block::bordered()
+        .title(format!("| {} |", dns_table.title))
+        .title_bottom(status)
+        .title_bottom(
+            report
+                .conclusion
+                .to_string()
+                .italic()
+                .into_right_aligned_line(),
+        );
+    if focused {
+        block = block.border_style(Style::default().fg(Color::Yellow));
+    }
+    Table::new(rows, widths)
+        .header(headers)
+        .highlight_style(Style::default().bg(Color::White).fg(Color::Black))
+        .highlight_symbol("> ")
+        .block(block)
+}
@@ -1,24 +1,271 @@
+use color_eyre::eyre::{bail, eyre};
+use reqwest::Url;
 use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
 
-#[derive(Debug)]
+const MAX_HOSTNAME_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Host {
+    Domain(String),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone)]
 pub struct Hostname {
-    hostname: String,
+    host: Host,
+    port: Option<u16>,
 }
 
 impl Hostname {
-    pub fn new(hostname: String) -> Self {
-        //  TODO: Hostname needs to be validated before running the traceroute
+    /// Parses a bare host, a `host:port` pair or a URL.
+    pub fn new(hostname: String) -> color_eyre::Result<Self> {
+        let input = hostname.trim();
+        if input.contains("://") {
+            let url = Url::parse(input).map_err(|e| eyre!("invalid URL {input:?}: {e}"))?;
+            let host = url
+                .host_str()
+                .ok_or_else(|| eyre!("invalid URL {input:?}: URL has no host"))?;
+            return Ok(Self {
+                host: parse_host(host)?,
+                port: url.port_or_known_default(),
+            });
+        }
+
+        let (host, port) = split_port(input)?;
+        Ok(Self {
+            host: parse_host(host)?,
+            port,
+        })
+    }
+
+    /// Returns the hostname as it should be resolved, i.e. in its ASCII (punycode) form.
+    pub fn hostname(&self) -> String {
+        match &self.host {
+            Host::Domain(domain) => domain.clone(),
+            Host::Ip(ip) => ip.to_string(),
+        }
+    }
 
-        Self { hostname }
+    /// Returns the port given with the hostname, if any.
+    pub fn port(&self) -> Option<u16> {
+        self.port
     }
 
-    pub fn hostname(&self) -> &str {
-        &self.hostname
+    /// Returns the address if the hostname is an IP literal and needs no lookup.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self.host {
+            Host::Domain(_) => None,
+            Host::Ip(ip) => Some(ip),
+        }
     }
 }
 
 impl Display for Hostname {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self.hostname)
+        write!(f, "{}", self.hostname())
+    }
+}
+
+/// Splits `host:port` and `[ipv6]:port` forms, leaving bare IPv6 literals intact.
+fn split_port(input: &str) -> color_eyre::Result<(&str, Option<u16>)> {
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| eyre!("invalid port {port:?} in {input:?}"))
+    };
+    if input.starts_with('[') {
+        if let Some((host, port)) = input.rsplit_once("]:") {
+            return Ok((&host[1..], Some(parse_port(port)?)));
+        }
+        return Ok((input, None));
+    }
+    match input.split_once(':') {
+        Some((host, port)) if !port.contains(':') => Ok((host, Some(parse_port(port)?))),
+        _ => Ok((input, None)),
+    }
+}
+
+fn parse_host(input: &str) -> color_eyre::Result<Host> {
+    if input.is_empty() {
+        bail!("invalid hostname: hostname is empty");
+    }
+    let literal = input
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(input);
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return Ok(Host::Ip(ip));
+    }
+
+    let domain = idna::domain_to_ascii(input)
+        .map_err(|e| eyre!("invalid hostname {input:?}: IDNA conversion failed ({e})"))?;
+    let domain = domain.strip_suffix('.').unwrap_or(&domain);
+    if domain.is_empty() {
+        bail!("invalid hostname {input:?}: hostname is empty");
+    }
+    if domain.len() > MAX_HOSTNAME_LEN {
+        bail!("invalid hostname {input:?}: longer than {MAX_HOSTNAME_LEN} characters");
+    }
+    for label in domain.split('.') {
+        validate_label(input, label)?;
+    }
+    if domain
+        .rsplit('.')
+        .next()
+        .is_some_and(|tld| tld.bytes().all(|b| b.is_ascii_digit()))
+    {
+        bail!("invalid hostname {input:?}: not a valid IP address or domain name");
+    }
+    Ok(Host::Domain(domain.to_string()))
+}
+
+/// Validates a single label according to RFC 1123.
+fn validate_label(input: &str, label: &str) -> color_eyre::Result<()> {
+    if label.is_empty() {
+        bail!("invalid hostname {input:?}: empty label");
+    }
+    if label.len() > MAX_LABEL_LEN {
+        bail!(
+            "invalid hostname {input:?}: label {label:?} is longer than {MAX_LABEL_LEN} characters"
+        );
+    }
+    if let Some(c) = label
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '-')
+    {
+        bail!("invalid hostname {input:?}: label {label:?} contains invalid character {c:?}");
+    }
+    if label.starts_with('-') || label.ends_with('-') {
+        bail!("invalid hostname {input:?}: label {label:?} starts or ends with a hyphen");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Hostname {
+        Hostname::new(input.to_string()).unwrap()
+    }
+
+    fn rejects(input: &str) -> bool {
+        Hostname::new(input.to_string()).is_err()
+    }
+
+    #[test]
+    fn accept_hostnames() {
+        assert_eq!(parse("discord.com").hostname(), "discord.com");
+        assert_eq!(parse("localhost").hostname(), "localhost");
+        assert_eq!(parse("a-b.c-d.example").hostname(), "a-b.c-d.example");
+        assert_eq!(parse("1.example").hostname(), "1.example");
+        assert_eq!(parse("discord.com").port(), None);
+        assert_eq!(parse("discord.com").ip(), None);
+    }
+
+    #[test]
+    fn normalize_hostnames() {
+        assert_eq!(parse("  Discord.COM  ").hostname(), "discord.com");
+        assert_eq!(parse("discord.com.").hostname(), "discord.com");
+    }
+
+    #[test]
+    fn convert_international_hostnames() {
+        assert_eq!(parse("bücher.example").hostname(), "xn--bcher-kva.example");
+        assert_eq!(
+            parse("xn--bcher-kva.example").hostname(),
+            "xn--bcher-kva.example"
+        );
+    }
+
+    #[test]
+    fn accept_ip_addresses() {
+        let ip = parse("192.0.2.1");
+        assert_eq!(ip.ip(), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(parse("::1").ip(), Some("::1".parse().unwrap()));
+        assert_eq!(parse("[2001:db8::1]").hostname(), "2001:db8::1");
+    }
+
+    #[test]
+    fn accept_ports() {
+        let host = parse("example.com:8080");
+        assert_eq!(host.hostname(), "example.com");
+        assert_eq!(host.port(), Some(8080));
+        assert_eq!(parse("192.0.2.1:53").port(), Some(53));
+        let ipv6 = parse("[2001:db8::1]:443");
+        assert_eq!(ipv6.hostname(), "2001:db8::1");
+        assert_eq!(ipv6.port(), Some(443));
+    }
+
+    #[test]
+    fn accept_urls() {
+        let url = parse("https://example.com/path?q=1");
+        assert_eq!(url.hostname(), "example.com");
+        assert_eq!(url.port(), Some(443));
+        assert_eq!(parse("http://example.com").port(), Some(80));
+        assert_eq!(parse("https://example.com:8443").port(), Some(8443));
+        let ipv6 = parse("http://[::1]:8080/");
+        assert_eq!(ipv6.ip(), Some("::1".parse().unwrap()));
+        assert_eq!(ipv6.port(), Some(8080));
+    }
+
+    #[test]
+    fn reject_invalid_labels() {
+        assert!(rejects(""));
+        assert!(rejects("   "));
+        assert!(rejects("."));
+        assert!(rejects("bad_host!"));
+        assert!(rejects("under_score.example"));
+        assert!(rejects("-leading.example"));
+        assert!(rejects("trailing-.example"));
+        assert!(rejects("double..dot"));
+        assert!(rejects(".leading.dot"));
+    }
+
+    #[test]
+    fn reject_numeric_names_that_are_not_addresses() {
+        assert!(rejects("example.123"));
+        assert!(rejects("256.0.0.1"));
+        assert!(rejects("1.2.3"));
+    }
+
+    #[test]
+    fn reject_long_names() {
+        assert!(rejects(&format!(
+            "{}.example",
+            "a".repeat(MAX_LABEL_LEN + 1)
+        )));
+        assert!(rejects(&vec!["a".repeat(MAX_LABEL_LEN); 4].join(".")));
+    }
+
+    #[test]
+    fn reject_invalid_ports() {
+        assert!(rejects("example.com:"));
+        assert!(rejects("example.com:http"));
+        assert!(rejects("example.com:65536"));
+        assert!(rejects("[2001:db8::1]:port"));
+        assert!(rejects("[2001:db8::1"));
+    }
+
+    #[test]
+    fn reject_invalid_urls() {
+        assert!(rejects("http://"));
+        assert!(rejects("file:///etc/hosts"));
+        assert!(rejects("http://under_score.example"));
+    }
+
+    #[test]
+    fn split_ports() {
+        assert_eq!(split_port("example.com").unwrap(), ("example.com", None));
+        assert_eq!(
+            split_port("example.com:80").unwrap(),
+            ("example.com", Some(80))
+        );
+        assert_eq!(split_port("::1").unwrap(), ("::1", None));
+        assert_eq!(split_port("[::1]").unwrap(), ("[::1]", None));
+        assert_eq!(split_port("[::1]:80").unwrap(), ("::1", Some(80)));
+        assert!(split_port("example.com:-1").is_err());
+        assert!(split_port("[::1]:").is_err());
     }
 }